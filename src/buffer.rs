@@ -0,0 +1,135 @@
+//! Interleaved multichannel sample buffer.
+
+use crate::error::{Error, Result};
+use crate::pan::PanLaw;
+use crate::wav::{self, WavOptions};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AudioBuffer {
+    /// Interleaved samples, `channels` values per frame.
+    pub samples: Vec<f32>,
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+impl AudioBuffer {
+    /// Silent buffer of `frames` frames.
+    pub fn new(channels: u16, sample_rate: u32, frames: usize) -> Result<AudioBuffer> {
+        if channels == 0 {
+            return Err(Error::invalid("AudioBuffer needs at least one channel."));
+        }
+        Ok(AudioBuffer { samples: vec![0.0; frames * channels as usize], channels, sample_rate })
+    }
+
+    pub fn from_mono(samples: Vec<f32>, sample_rate: u32) -> AudioBuffer {
+        AudioBuffer { samples, channels: 1, sample_rate }
+    }
+
+    /// Interleaves equal-length channels.
+    pub fn from_channels(channels: &[Vec<f32>], sample_rate: u32) -> Result<AudioBuffer> {
+        let frames = channels.iter().map(|c| c.len()).max().unwrap_or(0);
+        let mut buffer = AudioBuffer::new(channels.len() as u16, sample_rate, frames)?;
+        for (c, data) in channels.iter().enumerate() {
+            buffer.add_to_channel(c, 0, data, 1.0)?;
+        }
+        Ok(buffer)
+    }
+
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.frames() as f32 / self.sample_rate as f32
+    }
+
+    pub fn frame(&self, i: usize) -> &[f32] {
+        let c = self.channels as usize;
+        &self.samples[i * c..(i + 1) * c]
+    }
+
+    /// Copy of one channel.
+    pub fn channel(&self, channel: usize) -> Vec<f32> {
+        self.samples.iter().skip(channel).step_by(self.channels as usize).copied().collect()
+    }
+
+    /// Grows the buffer with silence so it holds at least `frames` frames.
+    pub fn ensure_frames(&mut self, frames: usize) {
+        if frames > self.frames() {
+            self.samples.resize(frames * self.channels as usize, 0.0);
+        }
+    }
+
+    /// Mixes `source` into one channel starting at frame `offset`.
+    pub fn add_to_channel(&mut self, channel: usize, offset: usize, source: &[f32], gain: f32) -> Result<()> {
+        let c = self.channels as usize;
+        if channel >= c {
+            return Err(Error::invalid(format!("Channel {} out of range for a {} channel buffer.", channel, c)));
+        }
+        self.ensure_frames(offset + source.len());
+        for (i, &x) in source.iter().enumerate() {
+            self.samples[(offset + i) * c + channel] += x * gain;
+        }
+        Ok(())
+    }
+
+    /// Mixes a mono `source` into a stereo buffer at `pan` using `law`.
+    pub fn add_panned(&mut self, offset: usize, source: &[f32], pan: f32, law: PanLaw) -> Result<()> {
+        if self.channels != 2 {
+            return Err(Error::invalid("Panning requires a stereo buffer."));
+        }
+        let (left, right) = law.gains(pan);
+        self.add_to_channel(0, offset, source, left)?;
+        self.add_to_channel(1, offset, source, right)
+    }
+
+    /// Averages all channels into a mono buffer.
+    pub fn to_mono(&self) -> AudioBuffer {
+        let c = self.channels as usize;
+        let samples = self.samples.chunks_exact(c)
+            .map(|frame| frame.iter().sum::<f32>() / c as f32)
+            .collect();
+        AudioBuffer::from_mono(samples, self.sample_rate)
+    }
+
+    /// Reads a WAV file of any supported format into a float buffer.
+    pub fn read_wav(filename: &str) -> Result<AudioBuffer> {
+        wav::read_wav(filename, false)
+    }
+
+    /// Writes the buffer as a 32-bit float WAV file.
+    pub fn write_wav(&self, filename: &str) -> Result<()> {
+        wav::write_wav(self, filename, &WavOptions::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interleaving() {
+        let buffer = AudioBuffer::from_channels(&[vec![1.0, 2.0, 3.0], vec![4.0, 5.0]], 44100).unwrap();
+        assert_eq!(buffer.frames(), 3);
+        assert_eq!(buffer.samples, vec![1.0, 4.0, 2.0, 5.0, 3.0, 0.0]);
+        assert_eq!(buffer.channel(1), vec![4.0, 5.0, 0.0]);
+        assert_eq!(buffer.frame(1), &[2.0, 5.0]);
+    }
+
+    #[test]
+    fn test_add_panned() {
+        let mut buffer = AudioBuffer::new(2, 44100, 1).unwrap();
+        buffer.add_panned(1, &[1.0, 1.0], -1.0, PanLaw::LinearSum).unwrap();
+        assert_eq!(buffer.frames(), 3);
+        assert_eq!(buffer.channel(0), vec![0.0, 1.0, 1.0]);
+        assert_eq!(buffer.channel(1), vec![0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_invalid_layout() {
+        assert!(AudioBuffer::new(0, 44100, 10).is_err());
+        let mut mono = AudioBuffer::new(1, 44100, 10).unwrap();
+        assert!(mono.add_to_channel(1, 0, &[1.0], 1.0).is_err());
+        assert!(mono.add_panned(0, &[1.0], 0.0, PanLaw::Linear).is_err());
+    }
+}
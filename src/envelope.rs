@@ -1,7 +1,30 @@
 //! Methods for creating slices of useful waveforms. 
 //! Intended to be applied as a modulator to phase, amplitude, or frequency.
 
-struct Envelope {
+use crate::error::{Error, Result};
+
+/// Shape of an envelope curve, evaluated at time `t` in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Curve {
+    Constant(f32),
+    Linear { slope: f32 },
+    Power { base: f32, pow: f32 },
+    Exponential { base: f32, pow: f32 },
+}
+
+impl Curve {
+    fn raw(&self, t: f32) -> f32 {
+        match *self {
+            Curve::Constant(x) => x,
+            Curve::Linear { slope } => slope * t,
+            Curve::Power { base, pow } => (t / base).powf(pow),
+            Curve::Exponential { base, pow } => base.powf(t * pow),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Envelope {
     n: usize,
     sample_rate: i32,
     flip: bool,
@@ -9,68 +32,277 @@ struct Envelope {
 }
 
 impl Envelope {
-    fn new(n: usize, sample_rate: i32, cps: f32, flip: bool) -> Envelope {
+    pub fn new(n: usize, sample_rate: i32, cps: f32, flip: bool) -> Result<Envelope> {
         if cps <= 0.0 {
-            panic!("CPS must be a tempo")
+            return Err(Error::invalid("CPS must be a tempo"));
+        }
+        if sample_rate <= 0 {
+            return Err(Error::invalid("Sample rate must be positive"));
         }
-        Envelope { n, sample_rate, cps, flip }
+        Ok(Envelope { n, sample_rate, cps, flip })
     }
 
-    fn normalize_and_flip(&self, samples: &mut Vec<f32>) {
-        let max_val: f32 = samples.iter().fold(0.0, |max, &val| max.max(val.abs()));
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    fn time(&self, i: usize) -> f32 {
+        i as f32 / self.sample_rate as f32
+    }
+
+    /// Divisor and sign applied to raw curve values.
+    /// All curves are monotonic, so the peak is found at one of the endpoints
+    /// and the normalization is known without rendering the whole envelope.
+    fn normalization(&self, curve: &Curve) -> (f32, f32) {
+        if let Curve::Constant(_) = curve {
+            return (1.0, 1.0);
+        }
         let sign = if self.flip { -1.0 } else { 1.0 };
-    
-        if max_val > 1.0 {
-            for sample in samples.iter_mut() {
-                *sample = sign * (*sample / max_val);
-            }
-        } else {
-            if self.flip {
-                for sample in samples.iter_mut() {
-                    *sample *= sign;
-                }
-            }
+        if self.n == 0 {
+            return (1.0, sign);
         }
+        let first = curve.raw(self.time(0)).abs();
+        let last = curve.raw(self.time(self.n - 1)).abs();
+        let max_val = first.max(last);
+        if max_val > 1.0 { (max_val, sign) } else { (1.0, sign) }
+    }
+
+    /// Value of the normalized curve at sample `i`.
+    pub fn value_at(&self, curve: Curve, i: usize) -> f32 {
+        let (divisor, sign) = self.normalization(&curve);
+        sign * (curve.raw(self.time(i)) / divisor)
     }
 
-    pub fn constant(&self, x: f32) -> Vec<f32> {
-        if x > 1.0 || x < -1.0 {
-            panic!("Modulation samples must be bound to [-1.0, 1.0].");
+    /// Lazily evaluated envelope, yielding `n` samples without allocating.
+    pub fn stream(&self, curve: Curve) -> Result<EnvelopeStream> {
+        if let Curve::Constant(x) = curve {
+            if !(-1.0..=1.0).contains(&x) {
+                return Err(Error::invalid("Modulation samples must be bound to [-1.0, 1.0]."));
+            }
         }
+        Ok(self.stream_unchecked(curve))
+    }
 
-        vec![x; self.n]
+    /// Only constant curves can be out of range; the others are normalized.
+    fn stream_unchecked(&self, curve: Curve) -> EnvelopeStream {
+        let (divisor, sign) = self.normalization(&curve);
+        EnvelopeStream { envelope: *self, curve, divisor, sign, pos: 0 }
+    }
+
+    pub fn constant(&self, x: f32) -> Result<Vec<f32>> {
+        Ok(self.stream(Curve::Constant(x))?.collect())
     }
 
     pub fn linear(&self, slope: f32) -> Vec<f32> {
-        let mut samples = Vec::with_capacity(self.n);
-        for i in 0..self.n {
-            let value = slope * (i as f32 / self.sample_rate as f32);
-            samples.push(value);
-        }
-        self.normalize_and_flip(&mut samples);
-        samples
+        self.stream_unchecked(Curve::Linear { slope }).collect()
     }
 
     pub fn power(&self, base: f32, pow: f32) -> Vec<f32> {
-        let mut samples = Vec::with_capacity(self.n);
-        for i in 0..self.n {
-            let t = i as f32 / self.sample_rate as f32;
-            let value = (t / base).powf(pow);
-            samples.push(value);
-        }
-        self.normalize_and_flip(&mut samples);
-        samples
+        self.stream_unchecked(Curve::Power { base, pow }).collect()
     }
 
     pub fn exponential(&self, base: f32, pow: f32) -> Vec<f32> {
-        let mut samples = Vec::with_capacity(self.n);
-        for i in 0..self.n {
-            let t = i as f32 / self.sample_rate as f32;
-            let value = base.powf(t * pow);
-            samples.push(value);
+        self.stream_unchecked(Curve::Exponential { base, pow }).collect()
+    }
+}
+
+/// Stateful reader over an envelope, usable as an iterator or as a block processor.
+#[derive(Clone, Debug)]
+pub struct EnvelopeStream {
+    envelope: Envelope,
+    curve: Curve,
+    divisor: f32,
+    sign: f32,
+    pos: usize,
+}
+
+impl EnvelopeStream {
+    fn value(&self, i: usize) -> f32 {
+        self.sign * (self.curve.raw(self.envelope.time(i)) / self.divisor)
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn reset(&mut self) {
+        self.pos = 0;
+    }
+
+    /// Writes the next `out.len()` samples and returns how many came from the curve.
+    /// Once the envelope is exhausted the remainder is held at the final value,
+    /// so open-ended notes can keep pulling blocks.
+    pub fn fill(&mut self, out: &mut [f32]) -> usize {
+        let remaining = self.envelope.n - self.pos;
+        let written = remaining.min(out.len());
+        for (k, sample) in out[..written].iter_mut().enumerate() {
+            *sample = self.value(self.pos + k);
+        }
+        self.pos += written;
+        let hold = if self.envelope.n == 0 { 0.0 } else { self.value(self.envelope.n - 1) };
+        for sample in out[written..].iter_mut() {
+            *sample = hold;
         }
-        self.normalize_and_flip(&mut samples);
-        samples
+        written
+    }
+}
+
+impl Iterator for EnvelopeStream {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.envelope.n {
+            return None;
+        }
+        let value = self.value(self.pos);
+        self.pos += 1;
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.envelope.n - self.pos;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for EnvelopeStream {}
+
+/// Composable envelope built from curves.
+/// Transforms are evaluated per sample, so compound shapes stay allocation-free
+/// apart from the nodes themselves.
+#[derive(Clone, Debug)]
+pub struct Shape {
+    node: Node,
+    /// Cached at construction so per-sample lookups don't walk the tree.
+    len: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Curve(EnvelopeStream),
+    Reverse(Box<Shape>),
+    Invert(Box<Shape>),
+    Range(Box<Shape>, f32, f32),
+    Stretch(Box<Shape>, f32),
+    Concat(Box<Shape>, Box<Shape>),
+    Mul(Box<Shape>, Box<Shape>),
+    Add(Box<Shape>, Box<Shape>),
+}
+
+impl Shape {
+    pub fn new(envelope: &Envelope, curve: Curve) -> Result<Shape> {
+        Ok(Shape::from_node(Node::Curve(envelope.stream(curve)?)))
+    }
+
+    fn from_node(node: Node) -> Shape {
+        let len = match &node {
+            Node::Curve(stream) => stream.envelope.n,
+            Node::Reverse(a) | Node::Invert(a) | Node::Range(a, _, _) => a.len,
+            Node::Stretch(a, factor) => (a.len as f32 * factor).round() as usize,
+            Node::Concat(a, b) => a.len + b.len,
+            Node::Mul(a, b) | Node::Add(a, b) => a.len.max(b.len),
+        };
+        Shape { node, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Value at sample `i`. Indices past the end hold the final value.
+    pub fn value_at(&self, i: usize) -> f32 {
+        let len = self.len;
+        if len == 0 {
+            return 0.0;
+        }
+        let i = i.min(len - 1);
+        match &self.node {
+            Node::Curve(stream) => stream.value(i),
+            Node::Reverse(a) => a.value_at(len - 1 - i),
+            Node::Invert(a) => 1.0 - a.value_at(i),
+            Node::Range(a, lo, hi) => lo + (hi - lo) * a.value_at(i),
+            Node::Stretch(a, factor) => {
+                let pos = i as f32 / factor;
+                let index = pos.floor() as usize;
+                let frac = pos - index as f32;
+                let x0 = a.value_at(index);
+                let x1 = a.value_at(index + 1);
+                x0 + (x1 - x0) * frac
+            }
+            Node::Concat(a, b) => {
+                if i < a.len { a.value_at(i) } else { b.value_at(i - a.len) }
+            }
+            Node::Mul(a, b) => a.value_at(i) * b.value_at(i),
+            Node::Add(a, b) => a.value_at(i) + b.value_at(i),
+        }
+    }
+
+    /// Plays the shape backwards, e.g. turning a rise into a decay.
+    pub fn reverse(self) -> Shape {
+        Shape::from_node(Node::Reverse(Box::new(self)))
+    }
+
+    /// Maps `x` to `1 - x`.
+    pub fn invert(self) -> Shape {
+        Shape::from_node(Node::Invert(Box::new(self)))
+    }
+
+    /// Maps the unit range onto `[lo, hi]`.
+    pub fn range(self, lo: f32, hi: f32) -> Shape {
+        Shape::from_node(Node::Range(Box::new(self), lo, hi))
+    }
+
+    /// Changes the length by `factor`, interpolating between samples.
+    pub fn stretch(self, factor: f32) -> Result<Shape> {
+        if !factor.is_finite() || factor <= 0.0 {
+            return Err(Error::invalid("Stretch factor must be finite and positive."));
+        }
+        Ok(Shape::from_node(Node::Stretch(Box::new(self), factor)))
+    }
+
+    /// Plays `next` after this shape ends.
+    pub fn then(self, next: Shape) -> Shape {
+        Shape::from_node(Node::Concat(Box::new(self), Box::new(next)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = f32> + '_ {
+        (0..self.len).map(move |i| self.value_at(i))
+    }
+
+    /// Block reader with the same hold-at-end behavior as `EnvelopeStream::fill`.
+    pub fn fill(&self, offset: usize, out: &mut [f32]) -> usize {
+        for (k, sample) in out.iter_mut().enumerate() {
+            *sample = self.value_at(offset + k);
+        }
+        self.len.saturating_sub(offset).min(out.len())
+    }
+
+    pub fn to_vec(&self) -> Vec<f32> {
+        self.iter().collect()
+    }
+}
+
+impl std::ops::Mul for Shape {
+    type Output = Shape;
+
+    fn mul(self, rhs: Shape) -> Shape {
+        Shape::from_node(Node::Mul(Box::new(self), Box::new(rhs)))
+    }
+}
+
+impl std::ops::Add for Shape {
+    type Output = Shape;
+
+    fn add(self, rhs: Shape) -> Shape {
+        Shape::from_node(Node::Add(Box::new(self), Box::new(rhs)))
     }
 }
 
@@ -83,14 +315,14 @@ mod tests {
 
     #[test]
     fn test_constant() {
-        let envelope = Envelope::new(5, 44100, 1.2, false);
-        let result = envelope.constant(0.5);
+        let envelope = Envelope::new(5, 44100, 1.2, false).unwrap();
+        let result = envelope.constant(0.5).unwrap();
         assert_eq!(result, vec![0.5, 0.5, 0.5, 0.5, 0.5]);
     }
 
     #[test]
     fn test_linear() {
-        let envelope = Envelope::new(5, 1, 1.2, false);
+        let envelope = Envelope::new(5, 1, 1.2, false).unwrap();
         let result = envelope.linear(1.0);
         let expected = vec![0.0, 0.25, 0.5, 0.75, 1.0];
         assert_eq!(result, expected);
@@ -106,7 +338,7 @@ mod tests {
         for _ in 0..n_runs {
             let base = rng.sample(base_range);
             let pow = rng.sample(pow_range);
-            let envelope = Envelope::new(30 * sample_rate, sample_rate as i32, 1.2, false);
+            let envelope = Envelope::new(30 * sample_rate, sample_rate as i32, 1.2, false).unwrap();
             let result = envelope.power(base, pow);
 
             for i in 0..(result.len() - 1) {
@@ -126,7 +358,7 @@ mod tests {
         for _ in 0..n_runs {
             let base = rng.sample(base_range);
             let pow = rng.sample(pow_range);
-            let envelope = Envelope::new(10, sample_rate, 1.2, true);
+            let envelope = Envelope::new(10, sample_rate, 1.2, true).unwrap();
             let result = envelope.power(base, pow);
 
             for i in 0..(result.len() - 1) {
@@ -137,7 +369,7 @@ mod tests {
 
     #[test]
     fn test_exponential_increasing() {
-        let envelope = Envelope::new(10, 1, 1.2, false);
+        let envelope = Envelope::new(10, 1, 1.2, false).unwrap();
         let result = envelope.exponential(2.0, 1.0);
         for i in 0..(result.len() - 1) {
             assert!(result[i] <= result[i + 1]);
@@ -146,7 +378,7 @@ mod tests {
     
     #[test]
     fn test_exponential_decreasing() {
-        let envelope = Envelope::new(10, 1, 1.2, true);
+        let envelope = Envelope::new(10, 1, 1.2, true).unwrap();
         let result = envelope.exponential(2.0, 1.0);
         for i in 0..(result.len() - 1) {
             assert!(result[i] >= result[i + 1]);
@@ -155,7 +387,7 @@ mod tests {
 
     #[test]
     fn test_flip() {
-        let envelope = Envelope::new(5, 1, 1.2, true);
+        let envelope = Envelope::new(5, 1, 1.2, true).unwrap();
         let result = envelope.linear(1.0);
         let expected = vec![0.0, -0.25, -0.5, -0.75, -1.0];
         assert_eq!(result, expected);
@@ -175,18 +407,98 @@ mod tests {
             let cps = rng.sample(cps_range);
             let base = rng.sample(base_range);
             let pow = rng.sample(pow_range);
-            let envelope = Envelope::new(4, sample_rate, cps, rng.gen_bool(0.5));
+            let envelope = Envelope::new(4, sample_rate, cps, rng.gen_bool(0.5)).unwrap();
             let result = envelope.exponential(base, pow);
 
-            assert!(result.iter().all(|&x| x >= -1.0 && x <= 1.0),
+            assert!(result.iter().all(|&x| (-1.0..=1.0).contains(&x)),
                 "Result not normalized: {:?}", result);
         }
     }
 
     #[test]
-    #[should_panic(expected = "Modulation samples must be bound to [-1.0, 1.0].")]
     fn test_constant_out_of_range() {
-        let envelope = Envelope::new(5, 44100, 1.2, false);
-        envelope.constant(1.5);
+        let envelope = Envelope::new(5, 44100, 1.2, false).unwrap();
+        assert!(matches!(envelope.constant(1.5), Err(Error::InvalidParameter(_))));
+    }
+
+    #[test]
+    fn test_invalid_tempo() {
+        assert!(Envelope::new(5, 44100, 0.0, false).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_stream_matches_vec() {
+        let envelope = Envelope::new(1000, 44100, 1.2, true).unwrap();
+        let expected = envelope.exponential(3.0, 2.0);
+        let streamed: Vec<f32> = envelope.stream(Curve::Exponential { base: 3.0, pow: 2.0 }).unwrap().collect();
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn test_fill_blocks() {
+        let envelope = Envelope::new(10, 1, 1.2, false).unwrap();
+        let expected = envelope.linear(1.0);
+        let mut stream = envelope.stream(Curve::Linear { slope: 1.0 }).unwrap();
+        let mut block = [0.0; 4];
+        let mut result = Vec::new();
+        let mut written = 0;
+        for _ in 0..3 {
+            written += stream.fill(&mut block);
+            result.extend_from_slice(&block);
+        }
+        assert_eq!(written, 10);
+        assert_eq!(&result[..10], &expected[..]);
+        assert_eq!(&result[10..], &[1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_shape_reverse_decay() {
+        let envelope = Envelope::new(5, 1, 1.2, false).unwrap();
+        let decay = Shape::new(&envelope, Curve::Linear { slope: 1.0 }).unwrap().reverse();
+        assert_eq!(decay.to_vec(), vec![1.0, 0.75, 0.5, 0.25, 0.0]);
+        let inverted = Shape::new(&envelope, Curve::Linear { slope: 1.0 }).unwrap().invert();
+        assert_eq!(inverted.to_vec(), decay.to_vec());
+    }
+
+    #[test]
+    fn test_shape_range_concat_stretch() {
+        let envelope = Envelope::new(5, 1, 1.2, false).unwrap();
+        let rise = Shape::new(&envelope, Curve::Linear { slope: 1.0 }).unwrap();
+        let ranged = rise.clone().range(0.5, 1.0);
+        assert_eq!(ranged.to_vec(), vec![0.5, 0.625, 0.75, 0.875, 1.0]);
+
+        let joined = rise.clone().then(rise.clone().reverse());
+        assert_eq!(joined.len(), 10);
+        assert_eq!(joined.value_at(4), 1.0);
+        assert_eq!(joined.value_at(9), 0.0);
+
+        let stretched = rise.clone().stretch(2.0).unwrap();
+        assert_eq!(stretched.len(), 10);
+        assert_eq!(stretched.value_at(1), 0.125);
+        assert_eq!(stretched.value_at(8), 1.0);
+        for factor in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(rise.clone().stretch(factor).is_err(), "{}", factor);
+        }
+    }
+
+    #[test]
+    fn test_shape_mul_add() {
+        let short = Envelope::new(3, 1, 1.2, false).unwrap();
+        let long = Envelope::new(5, 1, 1.2, false).unwrap();
+        let product = Shape::new(&long, Curve::Linear { slope: 1.0 }).unwrap() * Shape::new(&short, Curve::Constant(0.5)).unwrap();
+        assert_eq!(product.to_vec(), vec![0.0, 0.125, 0.25, 0.375, 0.5]);
+        let sum = Shape::new(&long, Curve::Constant(0.25)).unwrap() + Shape::new(&long, Curve::Constant(0.5)).unwrap();
+        assert_eq!(sum.to_vec(), vec![0.75; 5]);
+    }
+
+    #[test]
+    fn test_deep_shape() {
+        let envelope = Envelope::new(2, 1, 1.0, false).unwrap();
+        let step = Shape::new(&envelope, Curve::Linear { slope: 1.0 }).unwrap();
+        let chain = (1..2000).fold(step.clone(), |chain, _| chain.then(step.clone()));
+        assert_eq!(chain.len(), 4000);
+        let values = chain.to_vec();
+        assert_eq!(values.len(), 4000);
+        assert_eq!(values[3998..], [0.0, 1.0]);
+    }
+}
@@ -0,0 +1,73 @@
+//! Error type shared by the whole crate.
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A parameter was outside the range the operation accepts.
+    InvalidParameter(String),
+    Io(std::io::Error),
+    /// A file or sample format the crate cannot read or write.
+    UnsupportedFormat(String),
+    /// A frequency outside the configured `[min_frequency, max_frequency]`.
+    FrequencyOutOfRange(f32),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn invalid(message: impl Into<String>) -> Error {
+        Error::InvalidParameter(message.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidParameter(message) => write!(f, "invalid parameter: {}", message),
+            Error::Io(err) => write!(f, "i/o error: {}", err),
+            Error::UnsupportedFormat(message) => write!(f, "unsupported format: {}", message),
+            Error::FrequencyOutOfRange(freq) => write!(f, "frequency {} Hz is out of range", freq),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<hound::Error> for Error {
+    fn from(err: hound::Error) -> Error {
+        match err {
+            hound::Error::IoError(err) => Error::Io(err),
+            hound::Error::FormatError(message) => Error::UnsupportedFormat(message.to_string()),
+            hound::Error::Unsupported => Error::UnsupportedFormat(String::from("unsupported wav feature")),
+            other => Error::InvalidParameter(other.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_hound() {
+        let err: Error = hound::Error::TooWide.into();
+        assert!(matches!(err, Error::InvalidParameter(_)));
+        let err: Error = hound::Error::IoError(std::io::Error::other("disk full")).into();
+        assert!(matches!(err, Error::Io(_)));
+        assert_eq!(err.to_string(), "i/o error: disk full");
+    }
+}
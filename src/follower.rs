@@ -0,0 +1,145 @@
+//! Envelope follower for deriving modulation signals from rendered audio.
+//! The resulting control signal can duck, gate or bend another track.
+
+use crate::error::{Error, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Detection {
+    /// Tracks the rectified signal.
+    Peak,
+    /// Tracks mean power and reports its square root.
+    Rms,
+}
+
+#[derive(Clone, Debug)]
+pub struct EnvelopeFollower {
+    detection: Detection,
+    attack_coef: f32,
+    release_coef: f32,
+    state: f32,
+}
+
+/// One-pole smoothing coefficient for a time constant in seconds.
+fn coefficient(seconds: f32, sample_rate: u32) -> f32 {
+    if seconds <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / (seconds * sample_rate as f32)).exp()
+    }
+}
+
+impl EnvelopeFollower {
+    pub fn new(sample_rate: u32, detection: Detection, attack: f32, release: f32) -> Result<EnvelopeFollower> {
+        if sample_rate == 0 {
+            return Err(Error::invalid("Sample rate must be positive"));
+        }
+        if !attack.is_finite() || attack < 0.0 || !release.is_finite() || release < 0.0 {
+            return Err(Error::invalid("Attack and release must be finite and non-negative"));
+        }
+        Ok(EnvelopeFollower {
+            detection,
+            attack_coef: coefficient(attack, sample_rate),
+            release_coef: coefficient(release, sample_rate),
+            state: 0.0,
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.state = 0.0;
+    }
+
+    pub fn next_sample(&mut self, x: f32) -> f32 {
+        let input = match self.detection {
+            Detection::Peak => x.abs(),
+            Detection::Rms => x * x,
+        };
+        let coef = if input > self.state { self.attack_coef } else { self.release_coef };
+        self.state = coef * self.state + (1.0 - coef) * input;
+        match self.detection {
+            Detection::Peak => self.state,
+            Detection::Rms => self.state.sqrt(),
+        }
+    }
+
+    /// Block version of `next_sample`; state carries over between calls.
+    pub fn fill(&mut self, input: &[f32], out: &mut [f32]) {
+        for (y, &x) in out.iter_mut().zip(input) {
+            *y = self.next_sample(x);
+        }
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let mut out = vec![0.0; input.len()];
+        self.fill(input, &mut out);
+        out
+    }
+}
+
+/// Reduces `samples` by up to `depth` (0 to 1) wherever `control` is loud.
+pub fn duck(samples: &mut [f32], control: &[f32], depth: f32) {
+    for (sample, &c) in samples.iter_mut().zip(control) {
+        *sample *= 1.0 - depth * c.clamp(0.0, 1.0);
+    }
+}
+
+/// Silences `samples` wherever `control` is below `threshold`.
+pub fn gate(samples: &mut [f32], control: &[f32], threshold: f32) {
+    for (sample, &c) in samples.iter_mut().zip(control) {
+        if c < threshold {
+            *sample = 0.0;
+        }
+    }
+}
+
+/// Maps a control signal onto `[lo, hi]`, e.g. for a filter cutoff.
+pub fn map_range(control: &[f32], lo: f32, hi: f32) -> Vec<f32> {
+    control.iter().map(|&c| lo + (hi - lo) * c.clamp(0.0, 1.0)).collect()
+}
+
+/// Bends `freqs` upward by up to `depth` semitones following `control`.
+pub fn bend(freqs: &mut [f32], control: &[f32], depth: f32) {
+    for (freq, &c) in freqs.iter_mut().zip(control) {
+        *freq *= 2f32.powf(depth * c.clamp(0.0, 1.0) / 12.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peak_attack_release() {
+        let mut follower = EnvelopeFollower::new(1000, Detection::Peak, 0.0, 0.01).unwrap();
+        let mut input = vec![1.0; 10];
+        input.extend(vec![0.0; 100]);
+        let out = follower.process(&input);
+        assert_eq!(out[9], 1.0);
+        // One time constant after the signal stops the level has decayed to 1/e.
+        assert!((out[19] - (-1.0f32).exp()).abs() < 1e-3, "{}", out[19]);
+        assert!(out[109] < 0.01);
+    }
+
+    #[test]
+    fn test_rms_of_sine() {
+        let sr = 8000;
+        let input: Vec<f32> = (0..sr).map(|i| (2.0 * std::f32::consts::PI * 100.0 * i as f32 / sr as f32).sin()).collect();
+        let mut follower = EnvelopeFollower::new(sr, Detection::Rms, 0.05, 0.05).unwrap();
+        let out = follower.process(&input);
+        let level = out[out.len() - 1];
+        assert!((level - 0.5f32.sqrt()).abs() < 0.02, "{}", level);
+    }
+
+    #[test]
+    fn test_duck() {
+        let mut samples = vec![1.0; 4];
+        duck(&mut samples, &[0.0, 0.5, 1.0, 2.0], 0.5);
+        assert_eq!(samples, vec![1.0, 0.75, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_invalid_follower() {
+        assert!(EnvelopeFollower::new(0, Detection::Peak, 0.01, 0.01).is_err());
+        assert!(EnvelopeFollower::new(1000, Detection::Peak, -0.01, 0.01).is_err());
+        assert!(EnvelopeFollower::new(1000, Detection::Rms, 0.01, f32::NAN).is_err());
+    }
+}
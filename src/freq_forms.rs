@@ -1,5 +1,6 @@
 //! Generation of conventional waveforms using frequency-domain
 use std::f32::consts::PI;
+use crate::loudness;
 use crate::synth_config::SynthConfig;
 
 use std::collections::HashMap;
@@ -33,41 +34,52 @@ impl PhaseTracker {
 }
 
 
+/// Scales samples so the largest magnitude is 1.0, leaving silence untouched.
+/// See `loudness::normalize` for RMS and LUFS targets.
 pub fn normalize_waveform(samples: &mut [f32]) {
-    let (min, max) = samples.iter().fold((f32::MAX, f32::MIN), |(min, max), &val| {
-        (min.min(val), max.max(val))
-    });
-    let amplitude_range = max - min;
-    samples.iter_mut().for_each(|sample| {
-        *sample = (*sample - min) / amplitude_range * 2.0 - 1.0;
-    });
+    let max = loudness::peak(samples);
+    if max > 0.0 {
+        samples.iter_mut().for_each(|sample| *sample /= max);
+    }
 }
 
 pub fn sine(config: &SynthConfig, t: u32, freq: f32, bias: Option<f32>) -> f32 {
+    let phase = t as f32 * freq / config.sample_rate as f32;
+    sine_phase(config, phase, freq, bias)
+}
+
+pub fn sine_phase(config: &SynthConfig, phase: f32, freq: f32, bias: Option<f32>) -> f32 {
     let phase_offset = 0.0;
-    let t = t as f32 / config.sample_rate as f32;
-    (2.0 * PI * freq * t as f32 + phase_offset).sin() 
+    (2.0 * PI * phase + phase_offset).sin()
 }
 
 pub fn square(config: &SynthConfig, t: u32, freq: f32, bias: Option<f32>) -> f32 {
+    let phase = t as f32 * freq / config.sample_rate as f32;
+    square_phase(config, phase, freq, bias)
+}
+
+pub fn square_phase(config: &SynthConfig, phase: f32, freq: f32, bias: Option<f32>) -> f32 {
     let phase_offset = 0.0;
     let nyquist = config.sample_rate as f32 / 2.0;
     let max_harmonic = (nyquist / freq).floor() as i32;
-    let t = t as f32 / config.sample_rate as f32;
     (1..=max_harmonic).step_by(2).fold(0.0, |acc, n| {
-        acc + ((2.0 * PI * freq * n as f32 * t + phase_offset).sin() / n as f32)
+        acc + ((2.0 * PI * n as f32 * phase + phase_offset).sin() / n as f32)
     })
 }
 
 pub fn sawtooth(config: &SynthConfig, t: u32, freq: f32, bias: Option<f32>) -> f32 {
     let adjusted_freq = freq + config.tuning_offset_hz;
+    let phase = t as f32 * adjusted_freq / config.sample_rate as f32;
+    sawtooth_phase(config, phase, adjusted_freq, bias)
+}
+
+pub fn sawtooth_phase(config: &SynthConfig, phase: f32, freq: f32, bias: Option<f32>) -> f32 {
     let nyquist = config.sample_rate as f32 / 2.0;
-    let max_harmonic = (nyquist / adjusted_freq).floor() as i32;
-    let t = t as f32 / config.sample_rate as f32;
+    let max_harmonic = (nyquist / freq).floor() as i32;
     let mut sum = 0.0;
     for n in 1..=max_harmonic {
         let harmonic_bias = (n as f32 * bias.unwrap_or(0.5)).rem_euclid(1.0);
-        sum += (2.0 * std::f32::consts::PI * adjusted_freq * n as f32 * t + config.phase_offset + harmonic_bias).sin() / n as f32;
+        sum += (2.0 * PI * n as f32 * phase + config.phase_offset + harmonic_bias).sin() / n as f32;
     }
     // Normalize the sum to keep it within -1.0 to 1.0
     (sum / max_harmonic as f32) * config.amplitude_scaling
@@ -75,13 +87,17 @@ pub fn sawtooth(config: &SynthConfig, t: u32, freq: f32, bias: Option<f32>) -> f
 
 pub fn triangle(config: &SynthConfig, t: u32, freq: f32, bias: Option<f32>) -> f32 {
     let adjusted_freq = freq + config.tuning_offset_hz;
+    let phase = t as f32 * adjusted_freq / config.sample_rate as f32;
+    triangle_phase(config, phase, adjusted_freq, bias)
+}
+
+pub fn triangle_phase(config: &SynthConfig, phase: f32, freq: f32, bias: Option<f32>) -> f32 {
     let nyquist = config.sample_rate as f32 / 2.0;
-    let max_harmonic = (nyquist / adjusted_freq).floor() as i32;
-    let t = t as f32 / config.sample_rate as f32;
+    let max_harmonic = (nyquist / freq).floor() as i32;
     let mut sum = 0.0;
     for n in (1..=max_harmonic).step_by(2) {
         let harmonic_bias = (n as f32 * bias.unwrap_or(0.5)).rem_euclid(1.0);
-        sum += (2.0 * std::f32::consts::PI * adjusted_freq * n as f32 * t + config.phase_offset + harmonic_bias).sin() / (n as f32).powi(2);
+        sum += (2.0 * PI * n as f32 * phase + config.phase_offset + harmonic_bias).sin() / (n as f32).powi(2);
     }
     sum * config.amplitude_scaling
 }
@@ -108,13 +124,24 @@ mod tests {
     fn test_square_wave_basic() {
         let config = test_config();
         let sample = square(&config, 0, 440.0, Some(0.0));
-        assert!(sample >= -1.0 && sample <= 1.0, "Square wave sample is not within expected range.");
+        assert!((-1.0..=1.0).contains(&sample), "Square wave sample is not within expected range.");
     }
 
     #[test]
     fn test_sawtooth_wave_basic() {
         let config = test_config();
         let sample = sawtooth(&config, 0, 440.0, Some(0.5));
-        assert!(sample >= -1.0 && sample <= 1.0, "Sawtooth wave sample is not within expected range.");
+        assert!((-1.0..=1.0).contains(&sample), "Sawtooth wave sample is not within expected range.");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_normalize_waveform_keeps_dc_and_silence() {
+        let mut samples = vec![0.1, 0.2, 0.4];
+        normalize_waveform(&mut samples);
+        assert_eq!(samples, vec![0.25, 0.5, 1.0]);
+
+        let mut silence = vec![0.0; 4];
+        normalize_waveform(&mut silence);
+        assert_eq!(silence, vec![0.0; 4]);
+    }
+}
@@ -144,7 +144,7 @@ mod tests {
 
         for _ in 0..sample_rate as i32 {
             let sample = generator.next_sample();
-            assert!(sample >= -1.0 && sample <= 1.0, "Sample out of range");
+            assert!((-1.0..=1.0).contains(&sample), "Sample out of range");
         }
     }
 }
\ No newline at end of file
@@ -4,6 +4,26 @@ pub mod freq_forms;
 pub mod time_forms;
 pub mod synth_config;
 pub mod render;
+pub mod buffer;
+pub mod pan;
+pub mod wav;
+pub mod resample;
+pub mod stream;
+pub mod metadata;
+pub mod processor;
+pub mod loudness;
 pub mod gen;
 pub mod sequence;
-pub mod envelope;
\ No newline at end of file
+pub mod tempo;
+pub mod pattern;
+pub mod theory;
+pub mod step;
+pub mod voice;
+pub mod midi;
+pub mod tuning;
+pub mod envelope;
+pub mod error;
+pub mod oscillator;
+pub mod pitch;
+pub mod modulation;
+pub mod follower;
\ No newline at end of file
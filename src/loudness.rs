@@ -0,0 +1,290 @@
+//! Level measurement and normalization: sample peak, RMS, true peak and
+//! integrated loudness per ITU-R BS.1770 (K-weighting with absolute and
+//! relative gating), plus a true-peak aware limiter.
+
+use crate::buffer::AudioBuffer;
+use crate::error::{Error, Result};
+use crate::resample::{Quality, Resampler};
+
+const OVERSAMPLING: u32 = 4;
+
+pub fn to_db(gain: f32) -> f32 {
+    20.0 * gain.log10()
+}
+
+pub fn from_db(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Largest absolute sample value.
+pub fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0, |max, &x| max.max(x.abs()))
+}
+
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|&x| (x as f64).powi(2)).sum::<f64>() / samples.len() as f64).sqrt() as f32
+}
+
+/// Peak of the signal reconstructed at four times the sample rate, which
+/// catches inter-sample overs that sample peak misses.
+pub fn true_peak(buffer: &AudioBuffer) -> Result<f32> {
+    Ok(peak(&oversample(buffer)?))
+}
+
+fn oversample(buffer: &AudioBuffer) -> Result<Vec<f32>> {
+    let mut resampler = Resampler::new(buffer.sample_rate, buffer.sample_rate * OVERSAMPLING, buffer.channels, Quality::Medium)?;
+    let mut samples = resampler.process(&buffer.samples);
+    samples.extend(resampler.flush());
+    Ok(samples)
+}
+
+/// Second-order IIR section in direct form I.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+}
+
+impl Biquad {
+    fn run(&self, input: &[f64]) -> Vec<f64> {
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+        input.iter().map(|&x| {
+            let y = self.b[0] * x + self.b[1] * x1 + self.b[2] * x2 - self.a[1] * y1 - self.a[2] * y2;
+            x2 = x1;
+            x1 = x;
+            y2 = y1;
+            y1 = y;
+            y
+        }).collect()
+    }
+}
+
+/// K-weighting filters for any sample rate. The analog prototypes are the ones
+/// that reproduce the 48 kHz coefficients tabulated in BS.1770.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let highpass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+    [shelf, highpass]
+}
+
+/// Channel weights: surrounds in a 5.1 layout get +1.5 dB and the LFE is ignored.
+fn channel_weights(channels: u16) -> Vec<f64> {
+    if channels == 6 {
+        vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41]
+    } else {
+        vec![1.0; channels as usize]
+    }
+}
+
+/// Integrated loudness in LUFS. Returns negative infinity when every block is
+/// below the absolute gate, e.g. for silence or clips shorter than 400 ms.
+pub fn integrated_lufs(buffer: &AudioBuffer) -> f32 {
+    let sr = buffer.sample_rate as usize;
+    let block = sr * 4 / 10;
+    let step = sr / 10;
+    let frames = buffer.frames();
+    if frames < block || step == 0 {
+        return f32::NEG_INFINITY;
+    }
+    let [shelf, highpass] = k_weighting(buffer.sample_rate);
+    let filtered: Vec<Vec<f64>> = (0..buffer.channels as usize)
+        .map(|c| {
+            let x: Vec<f64> = buffer.channel(c).iter().map(|&s| s as f64).collect();
+            highpass.run(&shelf.run(&x)).iter().map(|y| y * y).collect()
+        })
+        .collect();
+    let weights = channel_weights(buffer.channels);
+
+    let block_power: Vec<f64> = (0..=(frames - block) / step)
+        .map(|j| {
+            let start = j * step;
+            filtered.iter().zip(&weights)
+                .map(|(power, w)| w * power[start..start + block].iter().sum::<f64>() / block as f64)
+                .sum()
+        })
+        .collect();
+    let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+
+    // Blocks must clear the absolute gate in both passes, as BS.1770 requires.
+    let gated_mean = |threshold: f64| {
+        let kept: Vec<f64> = block_power.iter().copied().filter(|&p| loudness(p) > -70.0 && loudness(p) > threshold).collect();
+        if kept.is_empty() { None } else { Some(kept.iter().sum::<f64>() / kept.len() as f64) }
+    };
+    let Some(absolute) = gated_mean(-70.0) else {
+        return f32::NEG_INFINITY;
+    };
+    match gated_mean(loudness(absolute) - 10.0) {
+        Some(power) => loudness(power) as f32,
+        None => f32::NEG_INFINITY,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    /// Sample peak in dBFS.
+    Peak(f32),
+    /// RMS over all channels in dBFS.
+    Rms(f32),
+    /// Integrated loudness in LUFS.
+    Lufs(f32),
+}
+
+/// Scales `buffer` to reach `target`, then limits true peaks to `ceiling` dBTP
+/// if given. Returns the gain applied before limiting; silent buffers are left
+/// untouched with a gain of one.
+pub fn normalize(buffer: &mut AudioBuffer, target: Target, ceiling: Option<f32>) -> Result<f32> {
+    let (current, wanted) = match target {
+        Target::Peak(db) => (to_db(peak(&buffer.samples)), db),
+        Target::Rms(db) => (to_db(rms(&buffer.samples)), db),
+        Target::Lufs(lufs) => (integrated_lufs(buffer), lufs),
+    };
+    let gain = if current.is_finite() { from_db(wanted - current) } else { 1.0 };
+    buffer.samples.iter_mut().for_each(|x| *x *= gain);
+    if let Some(ceiling) = ceiling {
+        limit_true_peak(buffer, ceiling)?;
+    }
+    Ok(gain)
+}
+
+/// Brickwall limiter driven by the oversampled peak, keeping the signal below
+/// `ceiling` dBTP. Gain reduction is applied ahead of each peak over a 1.5 ms
+/// window and released over 50 ms.
+pub fn limit_true_peak(buffer: &mut AudioBuffer, ceiling: f32) -> Result<()> {
+    if ceiling.is_nan() {
+        return Err(Error::invalid("Ceiling must be a number"));
+    }
+    let c = buffer.channels as usize;
+    let frames = buffer.frames();
+    let limit = from_db(ceiling);
+    let upsampled = oversample(buffer)?;
+    let os = OVERSAMPLING as usize;
+
+    // Gain each frame needs so neither it nor the following inter-sample points exceed the ceiling.
+    let required: Vec<f32> = (0..frames)
+        .map(|i| {
+            let start = i.saturating_sub(1) * os * c;
+            let end = ((i + 1) * os * c).min(upsampled.len());
+            let p = peak(&upsampled[start..end]).max(peak(&buffer.samples[i * c..(i + 1) * c]));
+            if p > limit { limit / p } else { 1.0 }
+        })
+        .collect();
+
+    let window = ((buffer.sample_rate as f32 * 0.0015) as usize).max(1);
+    // Forward-looking minimum followed by a trailing average of the same length
+    // never exceeds the requirement at any frame, but ramps in smoothly.
+    let lookahead: Vec<f32> = (0..frames)
+        .map(|i| required[i..(i + window).min(frames)].iter().fold(1.0f32, |m, &g| m.min(g)))
+        .collect();
+    let mut smoothed = Vec::with_capacity(frames);
+    let mut sum = 0.0f64;
+    for i in 0..frames {
+        sum += lookahead[i] as f64;
+        if i >= window {
+            sum -= lookahead[i - window] as f64;
+        }
+        let taken = (i + 1).min(window);
+        // Nothing precedes the first frame, so the average starts from its gain.
+        let padding = lookahead[0] as f64 * (window - taken) as f64;
+        smoothed.push(((sum + padding) / window as f64) as f32);
+    }
+
+    let release = (-1.0 / (0.05 * buffer.sample_rate as f32)).exp();
+    let mut gain = 1.0f32;
+    for (i, frame) in buffer.samples.chunks_exact_mut(c).enumerate() {
+        gain = smoothed[i].min(1.0 - (1.0 - gain) * release);
+        frame.iter_mut().for_each(|x| *x *= gain);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, amp: f32, sample_rate: u32, seconds: f32) -> Vec<f32> {
+        (0..(sample_rate as f32 * seconds) as usize)
+            .map(|i| amp * (2.0 * std::f32::consts::PI * freq * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_k_weighting_matches_spec_at_48k() {
+        let [shelf, highpass] = k_weighting(48000);
+        assert!((shelf.b[0] - 1.53512485958697).abs() < 1e-9);
+        assert!((shelf.a[1] + 1.69065929318241).abs() < 1e-9);
+        assert!((highpass.a[1] + 1.99004745483398).abs() < 1e-9);
+        assert!((highpass.a[2] - 0.99007225036621).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_lufs_of_reference_tone() {
+        // A 997 Hz sine at -20 dBFS reads -23 LUFS in mono and -20 LUFS on both channels of a stereo file.
+        let tone = sine(997.0, from_db(-20.0), 48000, 3.0);
+        let stereo = AudioBuffer::from_channels(&[tone.clone(), tone.clone()], 48000).unwrap();
+        let lufs = integrated_lufs(&stereo);
+        assert!((lufs - -20.0).abs() < 0.1, "{}", lufs);
+        let mono = AudioBuffer::from_mono(tone, 48000);
+        assert!((integrated_lufs(&mono) - -23.0).abs() < 0.1);
+        assert_eq!(integrated_lufs(&AudioBuffer::from_mono(vec![0.0; 48000], 48000)), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_blocks_below_absolute_gate_are_ignored() {
+        // Alternating 3 s stretches at -62 and -71.5 LUFS: only the louder stretches count.
+        let loud = sine(997.0, from_db(-59.0), 48000, 3.0);
+        let quiet = sine(997.0, from_db(-68.5), 48000, 3.0);
+        let samples: Vec<f32> = (0..6).flat_map(|i| if i % 2 == 0 { loud.clone() } else { quiet.clone() }).collect();
+        let lufs = integrated_lufs(&AudioBuffer::from_mono(samples, 48000));
+        assert!((lufs - -62.0).abs() < 0.5, "{}", lufs);
+    }
+
+    #[test]
+    fn test_normalize_targets() {
+        let mut buffer = AudioBuffer::from_mono(sine(440.0, 0.1, 44100, 1.0), 44100);
+        normalize(&mut buffer, Target::Peak(-1.0), None).unwrap();
+        assert!((to_db(peak(&buffer.samples)) - -1.0).abs() < 0.01);
+
+        normalize(&mut buffer, Target::Rms(-20.0), None).unwrap();
+        assert!((to_db(rms(&buffer.samples)) - -20.0).abs() < 0.01);
+
+        normalize(&mut buffer, Target::Lufs(-16.0), None).unwrap();
+        assert!((integrated_lufs(&buffer) - -16.0).abs() < 0.05);
+
+        let mut silence = AudioBuffer::from_mono(vec![0.0; 100], 44100);
+        assert_eq!(normalize(&mut silence, Target::Peak(0.0), None).unwrap(), 1.0);
+        assert!(silence.samples.iter().all(|&x| x == 0.0));
+    }
+
+    #[test]
+    fn test_true_peak_limiting() {
+        // fs/4 sine sampled at 45 degrees: every sample is 0.707 but the waveform peaks at 1.
+        let samples: Vec<f32> = (0..8000).map(|i| (std::f32::consts::FRAC_PI_2 * i as f32 + std::f32::consts::FRAC_PI_4).sin()).collect();
+        let mut buffer = AudioBuffer::from_mono(samples, 44100);
+        assert!(peak(&buffer.samples) < 0.71);
+        assert!(true_peak(&buffer).unwrap() > 0.98);
+
+        limit_true_peak(&mut buffer, -6.0).unwrap();
+        let tp = to_db(true_peak(&buffer).unwrap());
+        assert!(tp <= -5.9, "{}", tp);
+    }
+}
@@ -0,0 +1,479 @@
+//! WAV metadata chunks: sampler loops (`smpl`), cue markers (`cue ` with
+//! `LIST/adtl` labels), `LIST/INFO` tags and Broadcast WAV `bext`.
+//! `hound` only handles audio data, so chunks are appended to its output and
+//! parsed from the raw RIFF structure.
+
+use std::io::Cursor;
+use crate::buffer::AudioBuffer;
+use crate::error::{Error, Result};
+use crate::stream::{render_to_writer, BufferSource};
+use crate::wav::{read_wav_from, WavOptions};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SampleLoop {
+    /// First frame of the loop.
+    pub start: u32,
+    /// Last frame of the loop, inclusive.
+    pub end: u32,
+    /// Number of repetitions, 0 for infinite.
+    pub play_count: u32,
+}
+
+/// Contents of the `smpl` chunk.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SamplerInfo {
+    /// MIDI note at which the sample plays back unpitched.
+    pub root_note: u8,
+    /// Fine tuning above the root note, in cents.
+    pub fine_tune: f32,
+    pub loops: Vec<SampleLoop>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CueMarker {
+    pub id: u32,
+    /// Position in frames.
+    pub position: u32,
+    pub label: Option<String>,
+}
+
+/// `LIST/INFO` tags.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InfoTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub software: Option<String>,
+    pub comment: Option<String>,
+    pub date: Option<String>,
+}
+
+/// Broadcast WAV `bext` chunk, version 1.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Broadcast {
+    pub description: String,
+    pub originator: String,
+    pub originator_reference: String,
+    /// `yyyy-mm-dd`
+    pub origination_date: String,
+    /// `hh:mm:ss`
+    pub origination_time: String,
+    /// Sample count since midnight of the first frame.
+    pub time_reference: u64,
+    pub coding_history: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WavMetadata {
+    pub sampler: Option<SamplerInfo>,
+    pub cues: Vec<CueMarker>,
+    pub info: InfoTags,
+    pub broadcast: Option<Broadcast>,
+}
+
+const INFO_IDS: [&[u8; 4]; 5] = [b"INAM", b"IART", b"ISFT", b"ICMT", b"ICRD"];
+
+impl InfoTags {
+    fn fields(&self) -> [&Option<String>; 5] {
+        [&self.title, &self.artist, &self.software, &self.comment, &self.date]
+    }
+
+    fn fields_mut(&mut self) -> [&mut Option<String>; 5] {
+        [&mut self.title, &mut self.artist, &mut self.software, &mut self.comment, &mut self.date]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.fields().iter().all(|f| f.is_none())
+    }
+}
+
+fn push_u16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn push_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn push_chunk(out: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    out.extend_from_slice(id);
+    push_u32(out, body.len() as u32);
+    out.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Null-terminated text field.
+fn zstring(text: &str) -> Vec<u8> {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+/// Fixed-width text field, truncated or zero padded.
+fn fixed(out: &mut Vec<u8>, text: &str, width: usize) {
+    let bytes = text.as_bytes();
+    let n = bytes.len().min(width);
+    out.extend_from_slice(&bytes[..n]);
+    out.extend(std::iter::repeat_n(0, width - n));
+}
+
+fn smpl_chunk(sampler: &SamplerInfo, sample_rate: u32) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_u32(&mut body, 0); // manufacturer
+    push_u32(&mut body, 0); // product
+    push_u32(&mut body, (1e9 / sample_rate as f64).round() as u32);
+    push_u32(&mut body, sampler.root_note as u32);
+    push_u32(&mut body, (sampler.fine_tune.clamp(0.0, 99.99) / 100.0 * 4294967296.0) as u32);
+    push_u32(&mut body, 0); // SMPTE format
+    push_u32(&mut body, 0); // SMPTE offset
+    push_u32(&mut body, sampler.loops.len() as u32);
+    push_u32(&mut body, 0); // sampler data
+    for (i, l) in sampler.loops.iter().enumerate() {
+        push_u32(&mut body, i as u32);
+        push_u32(&mut body, 0); // forward loop
+        push_u32(&mut body, l.start);
+        push_u32(&mut body, l.end);
+        push_u32(&mut body, 0);
+        push_u32(&mut body, l.play_count);
+    }
+    body
+}
+
+fn cue_chunk(cues: &[CueMarker]) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_u32(&mut body, cues.len() as u32);
+    for cue in cues {
+        push_u32(&mut body, cue.id);
+        push_u32(&mut body, cue.position);
+        body.extend_from_slice(b"data");
+        push_u32(&mut body, 0);
+        push_u32(&mut body, 0);
+        push_u32(&mut body, cue.position);
+    }
+    body
+}
+
+fn adtl_chunk(cues: &[CueMarker]) -> Vec<u8> {
+    let mut body = b"adtl".to_vec();
+    for cue in cues {
+        if let Some(label) = &cue.label {
+            let mut labl = Vec::new();
+            push_u32(&mut labl, cue.id);
+            labl.extend(zstring(label));
+            push_chunk(&mut body, b"labl", &labl);
+        }
+    }
+    body
+}
+
+fn info_chunk(info: &InfoTags) -> Vec<u8> {
+    let mut body = b"INFO".to_vec();
+    for (id, field) in INFO_IDS.iter().zip(info.fields()) {
+        if let Some(text) = field {
+            push_chunk(&mut body, id, &zstring(text));
+        }
+    }
+    body
+}
+
+fn bext_chunk(bext: &Broadcast) -> Vec<u8> {
+    let mut body = Vec::new();
+    fixed(&mut body, &bext.description, 256);
+    fixed(&mut body, &bext.originator, 32);
+    fixed(&mut body, &bext.originator_reference, 32);
+    fixed(&mut body, &bext.origination_date, 10);
+    fixed(&mut body, &bext.origination_time, 8);
+    push_u32(&mut body, bext.time_reference as u32);
+    push_u32(&mut body, (bext.time_reference >> 32) as u32);
+    push_u16(&mut body, 1); // version
+    body.extend(std::iter::repeat_n(0, 64)); // UMID
+    body.extend(std::iter::repeat_n(0, 10)); // loudness values
+    body.extend(std::iter::repeat_n(0, 180)); // reserved
+    body.extend_from_slice(bext.coding_history.as_bytes());
+    body
+}
+
+/// Appends metadata chunks to a complete WAV file and fixes up the RIFF size.
+pub fn append_metadata(wav: &mut Vec<u8>, metadata: &WavMetadata) -> Result<()> {
+    let sample_rate = parse_chunks(wav)?
+        .into_iter()
+        .find(|(id, _)| id == b"fmt ")
+        .filter(|(_, body)| body.len() >= 16)
+        .map(|(_, body)| u32::from_le_bytes([body[4], body[5], body[6], body[7]]))
+        .ok_or_else(|| Error::UnsupportedFormat(String::from("missing or truncated fmt chunk")))?;
+    // hound leaves an odd-sized data chunk unpadded; chunks must start on an even offset.
+    // The pad byte belongs to the RIFF body only, so the data chunk size stays as written.
+    if wav.len() % 2 == 1 {
+        wav.push(0);
+    }
+    if let Some(sampler) = &metadata.sampler {
+        push_chunk(wav, b"smpl", &smpl_chunk(sampler, sample_rate));
+    }
+    if !metadata.cues.is_empty() {
+        push_chunk(wav, b"cue ", &cue_chunk(&metadata.cues));
+        if metadata.cues.iter().any(|c| c.label.is_some()) {
+            push_chunk(wav, b"LIST", &adtl_chunk(&metadata.cues));
+        }
+    }
+    if !metadata.info.is_empty() {
+        push_chunk(wav, b"LIST", &info_chunk(&metadata.info));
+    }
+    if let Some(bext) = &metadata.broadcast {
+        push_chunk(wav, b"bext", &bext_chunk(bext));
+    }
+    let riff_size = (wav.len() - 8) as u32;
+    wav[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(())
+}
+
+/// Top-level chunks of a RIFF/WAVE file as `(id, body)` pairs.
+fn parse_chunks(bytes: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(Error::UnsupportedFormat(String::from("not a RIFF/WAVE file")));
+    }
+    sub_chunks(&bytes[12..])
+}
+
+fn sub_chunks(mut bytes: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    let mut chunks = Vec::new();
+    while bytes.len() >= 8 {
+        let id = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        let size = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+        let end = 8 + size;
+        if end > bytes.len() {
+            return Err(Error::UnsupportedFormat(format!("truncated {} chunk", String::from_utf8_lossy(&id))));
+        }
+        chunks.push((id, &bytes[8..end]));
+        bytes = &bytes[(end + size % 2).min(bytes.len())..];
+    }
+    Ok(chunks)
+}
+
+fn read_u32(body: &[u8], at: usize) -> Result<u32> {
+    body.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| Error::UnsupportedFormat(String::from("truncated metadata chunk")))
+}
+
+fn text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Reads the record count at `at`, checking that many `size`-byte records fit after `offset`.
+fn record_count(body: &[u8], at: usize, offset: usize, size: usize, id: &str) -> Result<usize> {
+    let count = read_u32(body, at)? as usize;
+    if count > body.len().saturating_sub(offset) / size {
+        return Err(Error::UnsupportedFormat(format!("{} chunk is too short for {} records", id, count)));
+    }
+    Ok(count)
+}
+
+fn parse_smpl(body: &[u8]) -> Result<SamplerInfo> {
+    let num_loops = record_count(body, 28, 36, 24, "smpl")?;
+    let mut loops = Vec::with_capacity(num_loops);
+    for i in 0..num_loops {
+        let at = 36 + 24 * i;
+        loops.push(SampleLoop {
+            start: read_u32(body, at + 8)?,
+            end: read_u32(body, at + 12)?,
+            play_count: read_u32(body, at + 20)?,
+        });
+    }
+    Ok(SamplerInfo {
+        root_note: read_u32(body, 12)? as u8,
+        fine_tune: (read_u32(body, 16)? as f64 / 4294967296.0 * 100.0) as f32,
+        loops,
+    })
+}
+
+fn parse_bext(body: &[u8]) -> Result<Broadcast> {
+    if body.len() < 602 {
+        return Err(Error::UnsupportedFormat(String::from("truncated bext chunk")));
+    }
+    Ok(Broadcast {
+        description: text(&body[0..256]),
+        originator: text(&body[256..288]),
+        originator_reference: text(&body[288..320]),
+        origination_date: text(&body[320..330]),
+        origination_time: text(&body[330..338]),
+        time_reference: read_u32(body, 338)? as u64 | (read_u32(body, 342)? as u64) << 32,
+        coding_history: text(&body[602..]),
+    })
+}
+
+/// Reads all supported metadata chunks from a WAV file's bytes.
+pub fn read_metadata(bytes: &[u8]) -> Result<WavMetadata> {
+    let mut metadata = WavMetadata::default();
+    let mut labels = Vec::new();
+    for (id, body) in parse_chunks(bytes)? {
+        match &id {
+            b"smpl" => metadata.sampler = Some(parse_smpl(body)?),
+            b"cue " => {
+                let count = record_count(body, 0, 4, 24, "cue")?;
+                for i in 0..count {
+                    let at = 4 + 24 * i;
+                    metadata.cues.push(CueMarker { id: read_u32(body, at)?, position: read_u32(body, at + 20)?, label: None });
+                }
+            }
+            b"LIST" if body.len() >= 4 => {
+                let list = sub_chunks(&body[4..])?;
+                match &body[0..4] {
+                    b"adtl" => {
+                        for (_, data) in list.into_iter().filter(|(sub, _)| sub == b"labl") {
+                            labels.push((read_u32(data, 0)?, text(data.get(4..).unwrap_or_default())));
+                        }
+                    }
+                    b"INFO" => {
+                        for (sub, data) in list {
+                            if let Some(k) = INFO_IDS.iter().position(|id| **id == sub) {
+                                *metadata.info.fields_mut()[k] = Some(text(data));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            b"bext" => metadata.broadcast = Some(parse_bext(body)?),
+            _ => {}
+        }
+    }
+    for (id, label) in labels {
+        if let Some(cue) = metadata.cues.iter_mut().find(|c| c.id == id) {
+            cue.label = Some(label);
+        }
+    }
+    Ok(metadata)
+}
+
+/// Encodes `buffer` as WAV bytes followed by the metadata chunks.
+pub fn encode_wav_with_metadata(buffer: &AudioBuffer, options: &WavOptions, metadata: &WavMetadata) -> Result<Vec<u8>> {
+    let mut cursor = Cursor::new(Vec::new());
+    render_to_writer(&mut BufferSource::new(buffer), &mut cursor, options, 4096)?;
+    let mut bytes = cursor.into_inner();
+    append_metadata(&mut bytes, metadata)?;
+    Ok(bytes)
+}
+
+pub fn write_wav_with_metadata(buffer: &AudioBuffer, filename: &str, options: &WavOptions, metadata: &WavMetadata) -> Result<()> {
+    let bytes = encode_wav_with_metadata(buffer, options, metadata)?;
+    std::fs::write(filename, bytes)?;
+    Ok(())
+}
+
+/// Reads a WAV file's audio and metadata.
+pub fn read_wav_with_metadata(filename: &str) -> Result<(AudioBuffer, WavMetadata)> {
+    let bytes = std::fs::read(filename)?;
+    Ok((read_wav_from(&bytes[..], false)?, read_metadata(&bytes)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::WavFormat;
+
+    fn full_metadata() -> WavMetadata {
+        WavMetadata {
+            sampler: Some(SamplerInfo {
+                root_note: 57,
+                fine_tune: 25.0,
+                loops: vec![SampleLoop { start: 10, end: 89, play_count: 0 }],
+            }),
+            cues: vec![
+                CueMarker { id: 1, position: 0, label: Some(String::from("intro")) },
+                CueMarker { id: 2, position: 50, label: None },
+                CueMarker { id: 3, position: 75, label: Some(String::from("drop")) },
+            ],
+            info: InfoTags {
+                title: Some(String::from("Pad C3")),
+                artist: None,
+                software: Some(String::from("raudio-synth")),
+                comment: Some(String::from("odd")),
+                date: None,
+            },
+            broadcast: Some(Broadcast {
+                description: String::from("one-shot"),
+                originator: String::from("raudio-synth"),
+                origination_date: String::from("2024-01-02"),
+                origination_time: String::from("03:04:05"),
+                time_reference: 1 << 33,
+                coding_history: String::from("A=PCM,F=44100,W=24,M=mono"),
+                ..Broadcast::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let buffer = AudioBuffer::from_mono((0..100).map(|i| i as f32 / 100.0).collect(), 44100);
+        let metadata = full_metadata();
+        let bytes = encode_wav_with_metadata(&buffer, &WavOptions::default(), &metadata).unwrap();
+        assert_eq!(u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize, bytes.len() - 8);
+
+        let decoded = read_metadata(&bytes).unwrap();
+        let sampler = decoded.sampler.as_ref().unwrap();
+        assert_eq!(sampler.root_note, 57);
+        assert!((sampler.fine_tune - 25.0).abs() < 1e-3);
+        assert_eq!(decoded.cues, metadata.cues);
+        assert_eq!(decoded.info, metadata.info);
+        assert_eq!(decoded.broadcast, metadata.broadcast);
+
+        let audio = read_wav_from(&bytes[..], false).unwrap();
+        assert_eq!(audio, buffer);
+    }
+
+    #[test]
+    fn test_plain_wav_has_no_metadata() {
+        let buffer = AudioBuffer::from_mono(vec![0.0; 10], 8000);
+        let options = WavOptions::new(WavFormat::Int16);
+        let bytes = encode_wav_with_metadata(&buffer, &options, &WavMetadata::default()).unwrap();
+        assert_eq!(read_metadata(&bytes).unwrap(), WavMetadata::default());
+        assert!(read_metadata(b"RIFX").is_err());
+    }
+
+    #[test]
+    fn test_odd_data_chunk_is_padded() {
+        let buffer = AudioBuffer::from_mono((0..101).map(|i| i as f32 / 200.0).collect(), 8000);
+        let options = WavOptions::new(WavFormat::Int24);
+        let metadata = full_metadata();
+        let bytes = encode_wav_with_metadata(&buffer, &options, &metadata).unwrap();
+        assert_eq!(u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize, bytes.len() - 8);
+
+        let chunks = parse_chunks(&bytes).unwrap();
+        let data = chunks.iter().find(|(id, _)| id == b"data").unwrap();
+        assert_eq!(data.1.len(), 303);
+        assert!(chunks.iter().any(|(id, _)| id == b"smpl"));
+
+        let decoded = read_metadata(&bytes).unwrap();
+        assert_eq!(decoded.cues, metadata.cues);
+        assert_eq!(decoded.info, metadata.info);
+        assert_eq!(decoded.broadcast, metadata.broadcast);
+        assert_eq!(read_wav_from(&bytes[..], false).unwrap().frames(), 101);
+    }
+
+    #[test]
+    fn test_truncated_fmt_chunk() {
+        let mut bytes = b"RIFF\x14\0\0\0WAVEfmt \x04\0\0\0\x01\0\x01\0".to_vec();
+        assert!(matches!(append_metadata(&mut bytes, &full_metadata()), Err(Error::UnsupportedFormat(_))));
+    }
+
+    #[test]
+    fn test_hostile_record_counts() {
+        let wav = |id: &[u8], body: Vec<u8>| {
+            let mut bytes = b"RIFF".to_vec();
+            bytes.extend((12 + body.len() as u32).to_le_bytes());
+            bytes.extend(b"WAVE");
+            bytes.extend(id);
+            bytes.extend((body.len() as u32).to_le_bytes());
+            bytes.extend(body);
+            bytes
+        };
+        let mut smpl = vec![0; 36];
+        smpl[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(read_metadata(&wav(b"smpl", smpl)), Err(Error::UnsupportedFormat(_))));
+        let mut cue = vec![0; 28];
+        cue[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(read_metadata(&wav(b"cue ", cue)), Err(Error::UnsupportedFormat(_))));
+    }
+}
@@ -0,0 +1,641 @@
+//! Standard MIDI File import and export.
+//! Formats 0 and 1 are parsed into absolute-tick events, then converted to
+//! timeline notes using the file's tempo changes. Timelines are exported as
+//! format 1 files with one track per instrument.
+
+use std::collections::HashMap;
+use crate::buffer::AudioBuffer;
+use crate::error::{Error, Result};
+use crate::sequence::{Instrument, Note, Timeline};
+use crate::synth_config::SynthConfig;
+use crate::tuning;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Division {
+    TicksPerQuarter(u16),
+    /// Frames per second and ticks per frame.
+    Smpte { fps: u8, ticks_per_frame: u8 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MidiEvent {
+    NoteOn { channel: u8, key: u8, velocity: u8 },
+    NoteOff { channel: u8, key: u8, velocity: u8 },
+    ProgramChange { channel: u8, program: u8 },
+    /// Microseconds per quarter note.
+    Tempo(u32),
+    TimeSignature { numerator: u8, denominator: u8 },
+    EndOfTrack,
+    /// Any other channel, meta or system event, kept only for its timing.
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackEvent {
+    /// Absolute time in ticks.
+    pub tick: u64,
+    pub event: MidiEvent,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MidiFile {
+    pub format: u16,
+    pub division: Division,
+    pub tracks: Vec<Vec<TrackEvent>>,
+}
+
+/// Equal-tempered frequency of a MIDI key with A4 = 440 Hz.
+pub fn key_to_frequency(key: u8) -> f32 {
+    tuning::midi_to_frequency(key as f32, 440.0)
+}
+
+/// General MIDI percussion channel, kept free of melodic instruments on export.
+const DRUM_CHANNEL: u8 = 9;
+
+/// SMPTE frame rates a MIDI header can declare; 29 stands for 29.97 drop-frame.
+const SMPTE_RATES: [u8; 4] = [24, 25, 29, 30];
+
+/// Actual frame rate for a declared SMPTE rate.
+fn frames_per_second(fps: u8) -> f64 {
+    if fps == 29 { 30000.0 / 1001.0 } else { fps as f64 }
+}
+
+/// Tick-to-seconds conversion built once from a file's tempo changes.
+struct TickClock {
+    division: Division,
+    /// `(tick, seconds at tick, microseconds per quarter from tick on)`, starting at tick 0.
+    segments: Vec<(u64, f64, u32)>,
+}
+
+impl TickClock {
+    fn seconds(&self, tick: u64) -> f64 {
+        match self.division {
+            Division::Smpte { fps, ticks_per_frame } => tick as f64 / (frames_per_second(fps) * ticks_per_frame as f64),
+            Division::TicksPerQuarter(tpq) => {
+                let i = self.segments.partition_point(|s| s.0 <= tick) - 1;
+                let (at, seconds, tempo) = self.segments[i];
+                seconds + (tick - at) as f64 * tempo as f64 / 1e6 / tpq.max(1) as f64
+            }
+        }
+    }
+}
+
+/// A note-on waiting for its note-off.
+struct HeldNote {
+    tick: u64,
+    channel: u8,
+    key: u8,
+    velocity: u8,
+    program: u8,
+}
+
+/// Nearest MIDI key to a frequency, with A4 = 440 Hz.
+pub fn frequency_to_key(frequency: f32) -> u8 {
+    (69.0 + 12.0 * (frequency / 440.0).log2()).round().clamp(0.0, 127.0) as u8
+}
+
+fn push_vlq(out: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+fn encode_event(out: &mut Vec<u8>, event: &MidiEvent) {
+    match *event {
+        MidiEvent::NoteOn { channel, key, velocity } => out.extend([0x90 | channel, key, velocity]),
+        MidiEvent::NoteOff { channel, key, velocity } => out.extend([0x80 | channel, key, velocity]),
+        MidiEvent::ProgramChange { channel, program } => out.extend([0xc0 | channel, program]),
+        MidiEvent::Tempo(us) => {
+            out.extend([0xff, 0x51, 0x03]);
+            out.extend(&us.to_be_bytes()[1..]);
+        }
+        MidiEvent::TimeSignature { numerator, denominator } => {
+            out.extend([0xff, 0x58, 0x04, numerator, denominator.max(1).trailing_zeros() as u8, 24, 8]);
+        }
+        MidiEvent::EndOfTrack => out.extend([0xff, 0x2f, 0x00]),
+        MidiEvent::Other => {}
+    }
+}
+
+/// Largest delta time a four-byte variable-length quantity can hold.
+const MAX_DELTA: u64 = 0x0fff_ffff;
+
+fn push_delta(out: &mut Vec<u8>, delta: u64) -> Result<()> {
+    if delta > MAX_DELTA {
+        return Err(Error::invalid(format!("Delta time of {} ticks does not fit in a MIDI file", delta)));
+    }
+    push_vlq(out, delta as u32);
+    Ok(())
+}
+
+fn encode_track(events: &[TrackEvent]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut last = 0;
+    for e in events.iter().filter(|e| e.event != MidiEvent::Other && e.event != MidiEvent::EndOfTrack) {
+        push_delta(&mut out, e.tick - last)?;
+        encode_event(&mut out, &e.event);
+        last = e.tick;
+    }
+    let end = events.last().map_or(last, |e| e.tick.max(last));
+    push_delta(&mut out, end - last)?;
+    encode_event(&mut out, &MidiEvent::EndOfTrack);
+    Ok(out)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn truncated() -> Error {
+        Error::UnsupportedFormat(String::from("truncated MIDI data"))
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        let b = *self.bytes.get(self.pos).ok_or_else(Reader::truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let slice = self.bytes.get(self.pos..self.pos + n).ok_or_else(Reader::truncated)?;
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Variable-length quantity, at most four bytes.
+    fn vlq(&mut self) -> Result<u32> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let b = self.u8()?;
+            value = (value << 7) | (b & 0x7f) as u32;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::UnsupportedFormat(String::from("variable-length quantity too long")))
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+}
+
+fn parse_track(data: &[u8]) -> Result<Vec<TrackEvent>> {
+    let mut reader = Reader { bytes: data, pos: 0 };
+    let mut events = Vec::new();
+    let mut tick = 0u64;
+    let mut running_status: Option<u8> = None;
+    while !reader.done() {
+        tick += reader.vlq()? as u64;
+        let first = reader.u8()?;
+        let event = match first {
+            0xff => {
+                let kind = reader.u8()?;
+                let len = reader.vlq()? as usize;
+                let body = reader.take(len)?;
+                match (kind, body) {
+                    (0x51, [a, b, c]) => MidiEvent::Tempo(u32::from_be_bytes([0, *a, *b, *c])),
+                    (0x58, [n, d, ..]) => MidiEvent::TimeSignature { numerator: *n, denominator: 1u8.checked_shl(*d as u32).unwrap_or(0) },
+                    (0x2f, _) => MidiEvent::EndOfTrack,
+                    _ => MidiEvent::Other,
+                }
+            }
+            0xf0 | 0xf7 => {
+                let len = reader.vlq()? as usize;
+                reader.take(len)?;
+                running_status = None;
+                MidiEvent::Other
+            }
+            _ => {
+                let (status, data1) = if first & 0x80 != 0 {
+                    running_status = Some(first);
+                    (first, reader.u8()?)
+                } else {
+                    let status = running_status.ok_or_else(|| Error::UnsupportedFormat(String::from("data byte without running status")))?;
+                    (status, first)
+                };
+                let channel = status & 0x0f;
+                match status & 0xf0 {
+                    0x80 => MidiEvent::NoteOff { channel, key: data1, velocity: reader.u8()? },
+                    0x90 => {
+                        let velocity = reader.u8()?;
+                        if velocity == 0 {
+                            MidiEvent::NoteOff { channel, key: data1, velocity }
+                        } else {
+                            MidiEvent::NoteOn { channel, key: data1, velocity }
+                        }
+                    }
+                    0xa0 | 0xb0 | 0xe0 => {
+                        reader.u8()?;
+                        MidiEvent::Other
+                    }
+                    0xc0 => MidiEvent::ProgramChange { channel, program: data1 },
+                    0xd0 => MidiEvent::Other,
+                    _ => return Err(Error::UnsupportedFormat(format!("unexpected status byte {:#x}", status))),
+                }
+            }
+        };
+        let end = event == MidiEvent::EndOfTrack;
+        events.push(TrackEvent { tick, event });
+        if end {
+            break;
+        }
+    }
+    Ok(events)
+}
+
+impl MidiFile {
+    pub fn parse(bytes: &[u8]) -> Result<MidiFile> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(4)? != b"MThd" {
+            return Err(Error::UnsupportedFormat(String::from("not a Standard MIDI File")));
+        }
+        let header_len = reader.u32()? as usize;
+        let header = reader.take(header_len)?;
+        if header.len() < 6 {
+            return Err(Error::UnsupportedFormat(String::from("short MIDI header")));
+        }
+        let format = u16::from_be_bytes([header[0], header[1]]);
+        if format > 1 {
+            return Err(Error::UnsupportedFormat(format!("MIDI file format {}", format)));
+        }
+        let num_tracks = u16::from_be_bytes([header[2], header[3]]);
+        let division = match u16::from_be_bytes([header[4], header[5]]) {
+            d if d & 0x8000 == 0 => Division::TicksPerQuarter(d),
+            d => {
+                // The high byte holds the frame rate negated in two's complement.
+                let fps = 0u8.wrapping_sub((d >> 8) as u8);
+                if !SMPTE_RATES.contains(&fps) || d & 0xff == 0 {
+                    return Err(Error::UnsupportedFormat(format!("SMPTE division {:#06x}", d)));
+                }
+                Division::Smpte { fps, ticks_per_frame: (d & 0xff) as u8 }
+            }
+        };
+        let mut tracks = Vec::with_capacity(num_tracks as usize);
+        while tracks.len() < num_tracks as usize && !reader.done() {
+            let id = reader.take(4)?;
+            let len = reader.u32()? as usize;
+            let data = reader.take(len)?;
+            if id == b"MTrk" {
+                tracks.push(parse_track(data)?);
+            }
+        }
+        Ok(MidiFile { format, division, tracks })
+    }
+
+    pub fn read(filename: &str) -> Result<MidiFile> {
+        MidiFile::parse(&std::fs::read(filename)?)
+    }
+
+    /// Tempo changes from every track as `(tick, microseconds per quarter)`, in order.
+    fn tempo_changes(&self) -> Vec<(u64, u32)> {
+        let mut changes: Vec<(u64, u32)> = self.tracks.iter()
+            .flatten()
+            .filter_map(|e| match e.event {
+                MidiEvent::Tempo(us) => Some((e.tick, us)),
+                _ => None,
+            })
+            .collect();
+        changes.sort_by_key(|c| c.0);
+        changes
+    }
+
+    fn clock(&self) -> TickClock {
+        let tpq = match self.division {
+            Division::TicksPerQuarter(tpq) => tpq.max(1) as f64,
+            Division::Smpte { .. } => 1.0,
+        };
+        let mut segments = vec![(0u64, 0.0, 500_000u32)];
+        for (at, us) in self.tempo_changes() {
+            let (last_tick, seconds, tempo) = segments[segments.len() - 1];
+            let start = seconds + (at - last_tick) as f64 * tempo as f64 / 1e6 / tpq;
+            if at == last_tick {
+                segments.pop();
+            }
+            segments.push((at, start, us));
+        }
+        TickClock { division: self.division, segments }
+    }
+
+    /// Converts ticks to seconds, following tempo changes. The default tempo is 120 BPM.
+    pub fn seconds(&self, tick: u64) -> f64 {
+        self.clock().seconds(tick)
+    }
+
+    /// Pairs note-ons with note-offs and places them on a timeline.
+    /// `instrument_for(channel, program)` chooses the instrument index for each
+    /// note, or `None` to skip it. Notes still held at the end of a track are
+    /// released at its last event.
+    pub fn to_timeline(&self, instrument_for: &dyn Fn(u8, u8) -> Option<usize>) -> Timeline {
+        let mut timeline = Timeline::default();
+        let clock = self.clock();
+        for track in &self.tracks {
+            let mut programs = [0u8; 16];
+            let mut open: HashMap<(u8, u8), Vec<HeldNote>> = HashMap::new();
+            let close = |held: HeldNote, end: u64, timeline: &mut Timeline| {
+                if let Some(instrument) = instrument_for(held.channel, held.program) {
+                    let start = clock.seconds(held.tick);
+                    timeline.push(Note::new(
+                        start,
+                        clock.seconds(end) - start,
+                        key_to_frequency(held.key),
+                        held.velocity as f32 / 127.0,
+                        instrument,
+                    ));
+                }
+            };
+            for e in track {
+                match e.event {
+                    MidiEvent::ProgramChange { channel, program } => programs[channel as usize] = program,
+                    MidiEvent::NoteOn { channel, key, velocity } => {
+                        let program = programs[channel as usize];
+                        open.entry((channel, key)).or_default().push(HeldNote { tick: e.tick, channel, key, velocity, program });
+                    }
+                    MidiEvent::NoteOff { channel, key, .. } => {
+                        let stack = open.entry((channel, key)).or_default();
+                        if !stack.is_empty() {
+                            close(stack.remove(0), e.tick, &mut timeline);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            let last_tick = track.last().map_or(0, |e| e.tick);
+            let mut hanging: Vec<HeldNote> = open.into_values().flatten().collect();
+            hanging.sort_by_key(|held| (held.tick, held.channel, held.key));
+            for held in hanging {
+                close(held, last_tick, &mut timeline);
+            }
+        }
+        timeline.notes.sort_by(|a, b| a.start.total_cmp(&b.start));
+        timeline
+    }
+
+    /// Builds a format 1 file from a timeline.
+    /// The tempo is `config.cps * beats_per_cycle` quarter notes per second.
+    /// Each instrument gets its own track, and frequencies are rounded to the
+    /// nearest equal-tempered key. Instruments cycle through the fifteen melodic
+    /// channels, skipping channel 9 (the General MIDI drum channel), so
+    /// instrument 9 plays on channel 10 and instrument 15 wraps to channel 0.
+    pub fn from_timeline(timeline: &Timeline, config: &SynthConfig, beats_per_cycle: f64, ticks_per_quarter: u16) -> Result<MidiFile> {
+        if beats_per_cycle <= 0.0 || ticks_per_quarter == 0 || ticks_per_quarter & 0x8000 != 0 {
+            return Err(Error::invalid("Beats per cycle and ticks per quarter must be positive"));
+        }
+        let beats_per_second = config.cps as f64 * beats_per_cycle;
+        let tempo = (1e6 / beats_per_second).round() as u32;
+        if tempo == 0 || tempo > 0xff_ffff {
+            return Err(Error::invalid("Tempo cannot be represented in a MIDI file"));
+        }
+        let to_tick = |seconds: f64| (seconds * beats_per_second * ticks_per_quarter as f64).round().max(0.0) as u64;
+
+        let mut conductor = vec![
+            TrackEvent { tick: 0, event: MidiEvent::Tempo(tempo) },
+            TrackEvent { tick: 0, event: MidiEvent::TimeSignature { numerator: 4, denominator: 4 } },
+        ];
+        let mut instruments: Vec<usize> = timeline.notes.iter().map(|n| n.instrument).collect();
+        instruments.sort();
+        instruments.dedup();
+
+        let mut tracks = Vec::new();
+        let mut end = 0;
+        for instrument in instruments {
+            let channel = match (instrument % 15) as u8 {
+                c if c < DRUM_CHANNEL => c,
+                c => c + 1,
+            };
+            let mut events = Vec::new();
+            for note in timeline.notes.iter().filter(|n| n.instrument == instrument) {
+                let key = frequency_to_key(note.frequency);
+                let velocity = (note.velocity * 127.0).round().clamp(1.0, 127.0) as u8;
+                let start = to_tick(note.start);
+                // At least one tick long, so the note-off cannot sort ahead of its own note-on.
+                let stop = to_tick(note.start + note.duration).max(start + 1);
+                events.push(TrackEvent { tick: start, event: MidiEvent::NoteOn { channel, key, velocity } });
+                events.push(TrackEvent { tick: stop, event: MidiEvent::NoteOff { channel, key, velocity: 64 } });
+            }
+            // Note-offs sort ahead of note-ons on the same tick so repeated keys retrigger.
+            events.sort_by_key(|e| (e.tick, matches!(e.event, MidiEvent::NoteOn { .. })));
+            end = end.max(events.last().map_or(0, |e| e.tick));
+            events.push(TrackEvent { tick: events.last().map_or(0, |e| e.tick), event: MidiEvent::EndOfTrack });
+            tracks.push(events);
+        }
+        conductor.push(TrackEvent { tick: end, event: MidiEvent::EndOfTrack });
+        tracks.insert(0, conductor);
+        Ok(MidiFile { format: 1, division: Division::TicksPerQuarter(ticks_per_quarter), tracks })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut out = b"MThd".to_vec();
+        out.extend(6u32.to_be_bytes());
+        out.extend(self.format.to_be_bytes());
+        out.extend((self.tracks.len() as u16).to_be_bytes());
+        let division = match self.division {
+            Division::TicksPerQuarter(tpq) => tpq,
+            Division::Smpte { fps, ticks_per_frame } => {
+                if !SMPTE_RATES.contains(&fps) {
+                    return Err(Error::invalid(format!("SMPTE rate {} is not one of 24, 25, 29 or 30", fps)));
+                }
+                if ticks_per_frame == 0 {
+                    return Err(Error::invalid("SMPTE divisions need at least one tick per frame"));
+                }
+                (0u8.wrapping_sub(fps) as u16) << 8 | ticks_per_frame as u16
+            }
+        };
+        out.extend(division.to_be_bytes());
+        for track in &self.tracks {
+            let body = encode_track(track)?;
+            out.extend(b"MTrk");
+            out.extend((body.len() as u32).to_be_bytes());
+            out.extend(body);
+        }
+        Ok(out)
+    }
+
+    pub fn write(&self, filename: &str) -> Result<()> {
+        std::fs::write(filename, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Renders the file through `instruments`; see `to_timeline` for the mapping.
+    pub fn render(&self, config: &SynthConfig, instruments: &[&dyn Instrument], instrument_for: &dyn Fn(u8, u8) -> Option<usize>) -> Result<AudioBuffer> {
+        self.to_timeline(instrument_for).render(config, instruments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn file(format: u16, tracks: &[Vec<u8>], tpq: u16) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&format.to_be_bytes());
+        header.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        header.extend_from_slice(&tpq.to_be_bytes());
+        let mut out = chunk(b"MThd", &header);
+        for track in tracks {
+            out.extend(chunk(b"MTrk", track));
+        }
+        out
+    }
+
+    #[test]
+    fn test_format_0_running_status() {
+        let track = vec![
+            0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20, // 500000 us per quarter
+            0x00, 0x90, 60, 100,
+            0x00, 64, 80,             // running status note-on
+            0x83, 0x60, 60, 0,        // 480 ticks later, note-on with velocity 0
+            0x00, 64, 0,
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        let midi = MidiFile::parse(&file(0, &[track], 480)).unwrap();
+        assert_eq!(midi.format, 0);
+        assert_eq!(midi.division, Division::TicksPerQuarter(480));
+        let timeline = midi.to_timeline(&|_, _| Some(0));
+        assert_eq!(timeline.notes.len(), 2);
+        assert_eq!(timeline.notes[0], Note::new(0.0, 0.5, key_to_frequency(60), 100.0 / 127.0, 0));
+        assert_eq!(timeline.notes[1].frequency, key_to_frequency(64));
+        assert_eq!(timeline.notes[1].duration, 0.5);
+    }
+
+    #[test]
+    fn test_format_1_tempo_change_and_programs() {
+        let conductor = vec![
+            0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20, // 120 BPM
+            0x83, 0x60, 0xff, 0x51, 0x03, 0x0f, 0x42, 0x40, // 60 BPM after one beat
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        let melody = vec![
+            0x00, 0xc1, 5,
+            0x00, 0x91, 69, 127,
+            0x87, 0x40, 0x81, 69, 0, // note-off after two beats
+            0x00, 0x90, 57, 64,      // channel 0, program 0: skipped by the mapping
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        let midi = MidiFile::parse(&file(1, &[conductor, melody], 480)).unwrap();
+        assert_eq!(midi.tracks.len(), 2);
+        assert_eq!(midi.seconds(480), 0.5);
+        assert_eq!(midi.seconds(960), 1.5);
+
+        let timeline = midi.to_timeline(&|channel, program| if channel == 1 && program == 5 { Some(2) } else { None });
+        assert_eq!(timeline.notes, vec![Note::new(0.0, 1.5, 440.0, 1.0, 2)]);
+    }
+
+    #[test]
+    fn test_rejects_bad_data() {
+        assert!(MidiFile::parse(b"RIFF").is_err());
+        assert!(MidiFile::parse(&file(2, &[], 96)).is_err());
+        let orphan_data = vec![0x00, 60, 100];
+        assert!(MidiFile::parse(&file(0, &[orphan_data], 96)).is_err());
+    }
+
+    #[test]
+    fn test_smpte_division() {
+        let midi = MidiFile::parse(&file(0, &[], 0xe728)).unwrap();
+        assert_eq!(midi.division, Division::Smpte { fps: 25, ticks_per_frame: 40 });
+        assert_eq!(midi.seconds(1000), 1.0);
+        assert_eq!(midi.to_bytes().unwrap(), file(0, &[], 0xe728));
+        // 0x80 would negate to 128 frames per second; 0xe6 is 26.
+        assert!(MidiFile::parse(&file(0, &[], 0x8028)).is_err());
+        assert!(MidiFile::parse(&file(0, &[], 0xe628)).is_err());
+        assert!(MidiFile::parse(&file(0, &[], 0xe700)).is_err());
+        // -29 is 29.97 drop-frame.
+        let drop_frame = MidiFile::parse(&file(0, &[], 0xe301)).unwrap();
+        assert!((drop_frame.seconds(30000) - 1001.0).abs() < 1e-9);
+        let bad = MidiFile { division: Division::Smpte { fps: 26, ticks_per_frame: 40 }, ..midi.clone() };
+        assert!(bad.to_bytes().is_err());
+        let bad = MidiFile { division: Division::Smpte { fps: 25, ticks_per_frame: 0 }, ..midi };
+        assert!(bad.to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_vlq_encoding() {
+        for (value, bytes) in [(0u32, vec![0x00]), (0x7f, vec![0x7f]), (0x80, vec![0x81, 0x00]), (0x0fffffff, vec![0xff, 0xff, 0xff, 0x7f])] {
+            let mut out = Vec::new();
+            push_vlq(&mut out, value);
+            assert_eq!(out, bytes);
+            assert_eq!(Reader { bytes: &out, pos: 0 }.vlq().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let config = SynthConfig::new(44100, 20.0, 20000.0, 1.0, 0.0, 0.0, 0.5).unwrap();
+        let timeline = Timeline::new(vec![
+            Note::new(0.0, 0.5, 261.63, 0.8, 0),
+            Note::new(0.5, 0.5, 261.63, 0.8, 0),
+            Note::new(0.25, 1.0, 440.0, 0.5, 3),
+        ]);
+        let midi = MidiFile::from_timeline(&timeline, &config, 4.0, 480).unwrap();
+        // 0.5 cycles per second at 4 beats per cycle is 120 BPM.
+        assert_eq!(midi.tracks[0][0].event, MidiEvent::Tempo(500_000));
+        assert_eq!(midi.tracks.len(), 3);
+
+        let parsed = MidiFile::parse(&midi.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed, midi);
+        let back = parsed.to_timeline(&|channel, _| Some(channel as usize));
+        assert_eq!(back.notes.len(), 3);
+        assert_eq!(back.notes[0].frequency, key_to_frequency(60));
+        assert_eq!(back.notes[1], Note::new(0.25, 1.0, 440.0, 64.0 / 127.0, 3));
+        assert_eq!(back.notes[2].start, 0.5);
+        assert_eq!(back.notes[2].duration, 0.5);
+    }
+
+    #[test]
+    fn test_export_very_short_note() {
+        let config = SynthConfig::new(44100, 20.0, 20000.0, 1.0, 0.0, 0.0, 0.5).unwrap();
+        let timeline = Timeline::new(vec![Note::new(0.5, 0.0002, 440.0, 0.8, 0), Note::new(1.0, 0.5, 440.0, 0.8, 0)]);
+        let midi = MidiFile::from_timeline(&timeline, &config, 4.0, 480).unwrap();
+        let back = MidiFile::parse(&midi.to_bytes().unwrap()).unwrap().to_timeline(&|_, _| Some(0));
+        assert_eq!(back.notes.len(), 2);
+        // One tick at 120 BPM and 480 ticks per quarter.
+        assert!((back.notes[0].duration - 1.0 / 960.0).abs() < 1e-12);
+        assert_eq!(back.notes[1].duration, 0.5);
+    }
+
+    #[test]
+    fn test_export_skips_drum_channel() {
+        let config = SynthConfig::new(44100, 20.0, 20000.0, 1.0, 0.0, 0.0, 0.5).unwrap();
+        let timeline = Timeline::new([8, 9, 14, 15].iter().map(|&i| Note::new(0.0, 0.5, 440.0, 0.8, i)).collect());
+        let midi = MidiFile::from_timeline(&timeline, &config, 4.0, 480).unwrap();
+        let channels: Vec<u8> = midi.tracks[1..].iter()
+            .map(|t| match t[0].event {
+                MidiEvent::NoteOn { channel, .. } => channel,
+                _ => panic!("expected a note-on"),
+            })
+            .collect();
+        assert_eq!(channels, vec![8, 10, 15, 0]);
+    }
+
+    #[test]
+    fn test_export_rejects_long_delta() {
+        let config = SynthConfig::new(44100, 20.0, 20000.0, 1.0, 0.0, 0.0, 0.5).unwrap();
+        // 300000 seconds at 120 BPM and 480 ticks per quarter is 288000000 ticks.
+        let timeline = Timeline::new(vec![Note::new(300000.0, 0.5, 440.0, 0.8, 0)]);
+        let midi = MidiFile::from_timeline(&timeline, &config, 4.0, 480).unwrap();
+        assert!(midi.to_bytes().is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Note-level vibrato and tremolo with delayed onset.
+//! Both modulators are functions of time since the note started, so they can
+//! be applied to a frequency track before rendering or to any rendered buffer.
+
+use std::f32::consts::PI;
+use crate::error::{Error, Result};
+use crate::oscillator::Oscillator;
+use crate::render::PhaseUgen;
+use crate::synth_config::SynthConfig;
+
+/// Depth scaling in `[0, 1]`: silent until `delay`, then a linear fade over `fade_in` seconds.
+fn onset(t: f32, delay: f32, fade_in: f32) -> f32 {
+    if t < delay {
+        0.0
+    } else if fade_in <= 0.0 {
+        1.0
+    } else {
+        ((t - delay) / fade_in).min(1.0)
+    }
+}
+
+/// Rejects rates, depths and times that are negative or not finite.
+fn check_lfo(name: &str, rate: f32, depth: f32, delay: f32, fade_in: f32) -> Result<()> {
+    if [rate, depth, delay, fade_in].iter().any(|x| !x.is_finite() || *x < 0.0) {
+        return Err(Error::invalid(format!("{} rate, depth, delay and fade-in must be finite and non-negative", name)));
+    }
+    Ok(())
+}
+
+/// Periodic pitch modulation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vibrato {
+    /// LFO rate in Hz.
+    pub rate: f32,
+    /// Peak deviation in cents.
+    pub depth: f32,
+    /// Seconds before the vibrato starts.
+    pub delay: f32,
+    /// Seconds to reach full depth after the delay.
+    pub fade_in: f32,
+}
+
+impl Vibrato {
+    pub fn new(rate: f32, depth: f32, delay: f32, fade_in: f32) -> Result<Vibrato> {
+        check_lfo("Vibrato", rate, depth, delay, fade_in)?;
+        Ok(Vibrato { rate, depth, delay, fade_in })
+    }
+
+    /// Frequency multiplier `t` seconds into the note.
+    pub fn ratio_at(&self, t: f32) -> f32 {
+        let depth = self.depth * onset(t, self.delay, self.fade_in);
+        let lfo = (2.0 * PI * self.rate * (t - self.delay)).sin();
+        2f32.powf(depth * lfo / 1200.0)
+    }
+
+    /// Modulates a per-sample frequency track in place.
+    pub fn apply(&self, config: &SynthConfig, freqs: &mut [f32]) {
+        let sr = config.sample_rate as f32;
+        for (i, freq) in freqs.iter_mut().enumerate() {
+            *freq *= self.ratio_at(i as f32 / sr);
+        }
+    }
+}
+
+/// Periodic amplitude modulation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tremolo {
+    /// LFO rate in Hz.
+    pub rate: f32,
+    /// Deepest attenuation in dB.
+    pub depth: f32,
+    /// Seconds before the tremolo starts.
+    pub delay: f32,
+    /// Seconds to reach full depth after the delay.
+    pub fade_in: f32,
+}
+
+impl Tremolo {
+    pub fn new(rate: f32, depth: f32, delay: f32, fade_in: f32) -> Result<Tremolo> {
+        check_lfo("Tremolo", rate, depth, delay, fade_in)?;
+        Ok(Tremolo { rate, depth, delay, fade_in })
+    }
+
+    /// Gain `t` seconds into the note, between `-depth` dB and unity.
+    pub fn gain_at(&self, t: f32) -> f32 {
+        let depth = self.depth * onset(t, self.delay, self.fade_in);
+        let lfo = 0.5 - 0.5 * (2.0 * PI * self.rate * (t - self.delay)).cos();
+        10f32.powf(-depth * lfo / 20.0)
+    }
+
+    /// Modulates rendered samples in place.
+    pub fn apply(&self, config: &SynthConfig, samples: &mut [f32]) {
+        let sr = config.sample_rate as f32;
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample *= self.gain_at(i as f32 / sr);
+        }
+    }
+}
+
+/// Renders a single note of `duration` seconds with optional vibrato and tremolo.
+pub fn render_modulated(
+    config: &SynthConfig,
+    ugen: PhaseUgen,
+    freq: f32,
+    duration: f32,
+    vibrato: Option<&Vibrato>,
+    tremolo: Option<&Tremolo>,
+) -> Result<Vec<f32>> {
+    config.check_frequency(freq)?;
+    if !duration.is_finite() || duration < 0.0 {
+        return Err(Error::invalid("Duration must be finite and non-negative"));
+    }
+    if let Some(v) = vibrato {
+        check_lfo("Vibrato", v.rate, v.depth, v.delay, v.fade_in)?;
+    }
+    if let Some(t) = tremolo {
+        check_lfo("Tremolo", t.rate, t.depth, t.delay, t.fade_in)?;
+    }
+    let num_samples = (duration * config.sample_rate as f32).round() as usize;
+    let mut freqs = vec![freq; num_samples];
+    if let Some(vibrato) = vibrato {
+        vibrato.apply(config, &mut freqs);
+    }
+    let mut samples = vec![0.0; num_samples];
+    Oscillator::new(ugen, Some(0.5)).fill(config, &freqs, &mut samples);
+    if let Some(tremolo) = tremolo {
+        tremolo.apply(config, &mut samples);
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freq_forms;
+
+    #[test]
+    fn test_vibrato_delay_and_depth() {
+        let vibrato = Vibrato::new(5.0, 100.0, 0.5, 0.25).unwrap();
+        assert_eq!(vibrato.ratio_at(0.25), 1.0);
+        // Full depth is reached on an LFO crest, where the pitch peaks at +100 cents.
+        let peak = vibrato.ratio_at(0.75);
+        assert!((peak - 2f32.powf(1.0 / 12.0)).abs() < 1e-4, "{}", peak);
+    }
+
+    #[test]
+    fn test_tremolo_range() {
+        let tremolo = Tremolo::new(4.0, 6.0, 0.0, 0.0).unwrap();
+        assert_eq!(tremolo.gain_at(0.0), 1.0);
+        let trough = tremolo.gain_at(0.125);
+        assert!((trough - 10f32.powf(-6.0 / 20.0)).abs() < 1e-4, "{}", trough);
+    }
+
+    #[test]
+    fn test_render_modulated() {
+        let config = SynthConfig::new(8000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0).unwrap();
+        let vibrato = Vibrato::new(6.0, 50.0, 0.1, 0.1).unwrap();
+        let tremolo = Tremolo::new(3.0, 3.0, 0.0, 0.2).unwrap();
+        let samples = render_modulated(&config, freq_forms::sawtooth_phase, 220.0, 0.5, Some(&vibrato), Some(&tremolo)).unwrap();
+        assert_eq!(samples.len(), 4000);
+        assert!(samples.iter().all(|s| s.abs() <= 1.0));
+    }
+
+    #[test]
+    fn test_invalid_modulation() {
+        assert!(Vibrato::new(f32::NAN, 50.0, 0.0, 0.0).is_err());
+        assert!(Vibrato::new(5.0, 50.0, -0.1, 0.0).is_err());
+        assert!(Tremolo::new(-4.0, 6.0, 0.0, 0.0).is_err());
+        assert!(Tremolo::new(4.0, f32::INFINITY, 0.0, 0.0).is_err());
+        let config = SynthConfig::new(8000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0).unwrap();
+        let broken = Vibrato { rate: f32::NAN, ..Vibrato::new(5.0, 50.0, 0.0, 0.0).unwrap() };
+        assert!(render_modulated(&config, freq_forms::sine_phase, 220.0, 0.1, Some(&broken), None).is_err());
+        assert!(render_modulated(&config, freq_forms::sine_phase, -220.0, 0.1, None, None).is_err());
+        assert!(render_modulated(&config, freq_forms::sine_phase, 220.0, f32::NAN, None, None).is_err());
+    }
+}
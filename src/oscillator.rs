@@ -0,0 +1,65 @@
+//! Phase-accumulating oscillator.
+//! Unlike the `Ugen` functions, which derive phase from the sample index,
+//! the oscillator keeps its phase between samples so frequency can change
+//! mid-note without discontinuities.
+
+use crate::render::PhaseUgen;
+use crate::synth_config::SynthConfig;
+
+#[derive(Clone, Copy)]
+pub struct Oscillator {
+    ugen: PhaseUgen,
+    bias: Option<f32>,
+    phase: f64,
+}
+
+impl Oscillator {
+    pub fn new(ugen: PhaseUgen, bias: Option<f32>) -> Oscillator {
+        Oscillator { ugen, bias, phase: 0.0 }
+    }
+
+    /// Current phase in cycles, in `[0, 1)`.
+    pub fn phase(&self) -> f64 {
+        self.phase
+    }
+
+    pub fn set_phase(&mut self, phase: f64) {
+        self.phase = phase.rem_euclid(1.0);
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    /// Produces one sample at `freq` and advances the phase by one sample.
+    pub fn next_sample(&mut self, config: &SynthConfig, freq: f32) -> f32 {
+        let adjusted_freq = freq + config.tuning_offset_hz;
+        let sample = (self.ugen)(config, self.phase as f32, adjusted_freq, self.bias);
+        self.phase = (self.phase + adjusted_freq as f64 / config.sample_rate as f64).rem_euclid(1.0);
+        sample
+    }
+
+    /// Renders one sample per entry of `freqs` into `out`.
+    pub fn fill(&mut self, config: &SynthConfig, freqs: &[f32], out: &mut [f32]) {
+        for (sample, &freq) in out.iter_mut().zip(freqs) {
+            *sample = self.next_sample(config, freq);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_forms;
+
+    #[test]
+    fn test_matches_time_form() {
+        let config = SynthConfig::new(48000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0).unwrap();
+        let mut osc = Oscillator::new(time_forms::sine_phase, None);
+        for t in 0..480 {
+            let expected = time_forms::sine(&config, t, 100.0, None);
+            let sample = osc.next_sample(&config, 100.0);
+            assert!((sample - expected).abs() < 1e-3, "t={} {} != {}", t, sample, expected);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Pan laws for placing mono sources in a stereo field.
+
+use std::f32::consts::FRAC_PI_2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PanLaw {
+    /// Balance control: unity at center, the far side fades out linearly.
+    Linear,
+    /// Sine/cosine law, -3 dB at center.
+    ConstantPower,
+    /// Geometric mean of the constant-power and -6 dB laws, -4.5 dB at center.
+    Compromise,
+    /// Gains sum to one, -6 dB at center.
+    LinearSum,
+}
+
+impl PanLaw {
+    /// Left and right gains for `pan` in `[-1, 1]`, where -1 is hard left.
+    pub fn gains(&self, pan: f32) -> (f32, f32) {
+        let pan = pan.clamp(-1.0, 1.0);
+        let x = (pan + 1.0) / 2.0;
+        match self {
+            PanLaw::Linear => ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0)),
+            PanLaw::ConstantPower => ((x * FRAC_PI_2).cos(), (x * FRAC_PI_2).sin()),
+            PanLaw::Compromise => (
+                ((x * FRAC_PI_2).cos() * (1.0 - x)).sqrt(),
+                ((x * FRAC_PI_2).sin() * x).sqrt(),
+            ),
+            PanLaw::LinearSum => (1.0 - x, x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db(gain: f32) -> f32 {
+        20.0 * gain.log10()
+    }
+
+    #[test]
+    fn test_center_attenuation() {
+        let (l, r) = PanLaw::Linear.gains(0.0);
+        assert_eq!((l, r), (1.0, 1.0));
+        let (l, r) = PanLaw::ConstantPower.gains(0.0);
+        assert!((db(l) + 3.01).abs() < 0.01 && (l - r).abs() < 1e-6);
+        let (l, _) = PanLaw::Compromise.gains(0.0);
+        assert!((db(l) + 4.52).abs() < 0.01);
+        let (l, _) = PanLaw::LinearSum.gains(0.0);
+        assert!((db(l) + 6.02).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_hard_pan() {
+        for law in [PanLaw::Linear, PanLaw::ConstantPower, PanLaw::Compromise, PanLaw::LinearSum] {
+            let (l, r) = law.gains(-1.0);
+            assert!((l - 1.0).abs() < 1e-6 && r.abs() < 1e-6, "{:?}", law);
+            let (l, r) = law.gains(1.0);
+            assert!(l.abs() < 1e-6 && (r - 1.0).abs() < 1e-6, "{:?}", law);
+        }
+    }
+}
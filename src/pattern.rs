@@ -0,0 +1,354 @@
+//! Cycle-based pattern mini-notation in the style of TidalCycles.
+//!
+//! A pattern string describes one cycle: `"c4 e4 [g4 b4] ~ <a3 f3>"` plays
+//! five equal steps, the third split into two, a rest, and an alternation
+//! that picks `a3` on even cycles and `f3` on odd ones. Supported syntax:
+//!
+//! - `[a b]` subdivides a step; `[a, b]` stacks layers in parallel.
+//! - `<a b>` plays one element per cycle.
+//! - `~` is a rest.
+//! - `a*3` repeats a step three times within its slot.
+//! - `a!3` (or `a ! !`) repeats it as three separate steps.
+//! - `a(3,8)` and `a(3,8,2)` place it on a Euclidean rhythm, optionally rotated.
+//!
+//! Steps are note names (`c4`, `f#3`) or MIDI key numbers.
+
+use crate::error::{Error, Result};
+use crate::sequence::{Note, Timeline};
+use crate::tempo::TempoMap;
+use crate::tuning::{note_name_to_key, Tuning};
+use crate::voice::{EventKind, VoiceEvent};
+
+/// A note event; `start` and `duration` are in cycles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Event {
+    pub start: f64,
+    pub duration: f64,
+    pub key: u8,
+}
+
+/// Places events on a timeline, with keys tuned by `tuning` and cycles timed by `tempo`.
+pub fn to_timeline(events: &[Event], tempo: &TempoMap, tuning: &Tuning, velocity: f32, instrument: usize) -> Result<Timeline> {
+    let notes = events.iter().map(|event| {
+        let frequency = tuning.frequency(event.key)?;
+        Ok(Note::new(event.start, event.duration, frequency, velocity, instrument))
+    }).collect::<Result<Vec<Note>>>()?;
+    Ok(tempo.timeline(&notes))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Key(u8),
+    Rest,
+    Sequence(Vec<Node>),
+    Stack(Vec<Node>),
+    Alternate(Vec<Node>),
+    Fast(Box<Node>, u32),
+    Euclid { node: Box<Node>, pulses: usize, steps: usize, rotation: usize },
+}
+
+impl Node {
+    /// Adds events for this node's `cycle`-th cycle, squeezed into `[start, start + length)`.
+    fn query(&self, cycle: i64, start: f64, length: f64, out: &mut Vec<Event>) {
+        match self {
+            Node::Key(key) => out.push(Event { start, duration: length, key: *key }),
+            Node::Rest => {}
+            Node::Sequence(steps) => {
+                let step = length / steps.len() as f64;
+                for (i, node) in steps.iter().enumerate() {
+                    node.query(cycle, start + i as f64 * step, step, out);
+                }
+            }
+            Node::Stack(layers) => {
+                for node in layers {
+                    node.query(cycle, start, length, out);
+                }
+            }
+            Node::Alternate(choices) => {
+                let n = choices.len() as i64;
+                choices[cycle.rem_euclid(n) as usize].query(cycle.div_euclid(n), start, length, out);
+            }
+            Node::Fast(node, factor) => {
+                let step = length / *factor as f64;
+                for i in 0..*factor {
+                    node.query(cycle * *factor as i64 + i as i64, start + i as f64 * step, step, out);
+                }
+            }
+            Node::Euclid { node, pulses, steps, rotation } => {
+                let step = length / *steps as f64;
+                for (i, hit) in euclid(*pulses, *steps).into_iter().cycle().skip(*rotation).take(*steps).enumerate() {
+                    if hit {
+                        node.query(cycle, start + i as f64 * step, step, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Bjorklund's algorithm: `pulses` onsets spread as evenly as possible over `steps`.
+fn euclid(pulses: usize, steps: usize) -> Vec<bool> {
+    let mut a: Vec<Vec<bool>> = vec![vec![true]; pulses];
+    let mut b: Vec<Vec<bool>> = vec![vec![false]; steps - pulses];
+    while b.len() > 1 && !a.is_empty() {
+        let m = a.len().min(b.len());
+        let rest = if a.len() > m { a.split_off(m) } else { b.split_off(m) };
+        for (x, y) in a.iter_mut().zip(b.drain(..)) {
+            x.extend(y);
+        }
+        b = rest;
+    }
+    a.into_iter().chain(b).flatten().collect()
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> Error {
+        Error::invalid(format!("{} at offset {} in pattern {:?}", message, self.pos, self.text))
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.text[self.pos..];
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        trimmed.chars().next()
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("Expected '{}'", c)));
+        }
+        self.pos += c.len_utf8();
+        Ok(())
+    }
+
+    fn word(&mut self) -> &'a str {
+        self.peek();
+        let rest = &self.text[self.pos..];
+        let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '#' || c == '-' || c == '.')).unwrap_or(rest.len());
+        self.pos += end;
+        &rest[..end]
+    }
+
+    fn number(&mut self) -> Result<usize> {
+        self.word().parse().map_err(|_| self.error("Expected a whole number"))
+    }
+
+    /// Steps up to `close`, split into comma-separated layers.
+    fn layers(&mut self, close: Option<char>) -> Result<Vec<Vec<Node>>> {
+        let mut layers = Vec::new();
+        let mut steps: Vec<Node> = Vec::new();
+        loop {
+            match self.peek() {
+                c if c == close => break,
+                None => return Err(self.error("Unclosed group")),
+                Some(',') => {
+                    self.pos += 1;
+                    layers.push(std::mem::take(&mut steps));
+                }
+                Some('!') => {
+                    self.pos += 1;
+                    let last = steps.last().cloned().ok_or_else(|| self.error("Nothing to repeat"))?;
+                    let count = if self.text[self.pos..].starts_with(|c: char| c.is_ascii_digit()) { self.number()? } else { 2 };
+                    for _ in 1..count {
+                        steps.push(last.clone());
+                    }
+                }
+                Some(_) => steps.push(self.step()?),
+            }
+        }
+        layers.push(steps);
+        if layers.iter().any(|steps| steps.is_empty()) {
+            return Err(self.error("Empty group"));
+        }
+        Ok(layers)
+    }
+
+    fn sequence(&mut self, close: Option<char>) -> Result<Node> {
+        let mut layers: Vec<Node> = self.layers(close)?.into_iter().map(Node::Sequence).collect();
+        Ok(if layers.len() == 1 { layers.remove(0) } else { Node::Stack(layers) })
+    }
+
+    fn step(&mut self) -> Result<Node> {
+        let mut node = match self.peek() {
+            Some('[') => {
+                self.pos += 1;
+                let node = self.sequence(Some(']'))?;
+                self.expect(']')?;
+                node
+            }
+            Some('<') => {
+                self.pos += 1;
+                let mut layers = self.layers(Some('>'))?;
+                self.expect('>')?;
+                if layers.len() != 1 {
+                    return Err(self.error("Alternations cannot be stacked"));
+                }
+                Node::Alternate(layers.remove(0))
+            }
+            Some('~') => {
+                self.pos += 1;
+                Node::Rest
+            }
+            _ => {
+                let word = self.word();
+                if word.is_empty() {
+                    return Err(self.error("Unexpected character"));
+                }
+                match word.parse::<u8>() {
+                    Ok(key) if key < 128 => Node::Key(key),
+                    _ => Node::Key(note_name_to_key(word)?),
+                }
+            }
+        };
+        loop {
+            if self.text[self.pos..].starts_with('*') {
+                self.pos += 1;
+                let factor = self.number()?;
+                if factor == 0 {
+                    return Err(self.error("Repeat factor must be positive"));
+                }
+                node = Node::Fast(Box::new(node), factor as u32);
+            } else if self.text[self.pos..].starts_with('(') {
+                self.pos += 1;
+                let pulses = self.number()?;
+                self.expect(',')?;
+                let steps = self.number()?;
+                let rotation = if self.peek() == Some(',') {
+                    self.pos += 1;
+                    self.number()?
+                } else {
+                    0
+                };
+                self.expect(')')?;
+                if steps == 0 || pulses > steps {
+                    return Err(self.error("Euclidean rhythms need 0 <= pulses <= steps"));
+                }
+                node = Node::Euclid { node: Box::new(node), pulses, steps, rotation };
+            } else {
+                return Ok(node);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    root: Node,
+}
+
+impl Pattern {
+    pub fn parse(text: &str) -> Result<Pattern> {
+        let mut parser = Parser { text, pos: 0 };
+        let root = parser.sequence(None)?;
+        Ok(Pattern { root })
+    }
+
+    /// Events of cycle `cycle`, sorted by start time.
+    pub fn query(&self, cycle: i64) -> Vec<Event> {
+        let mut events = Vec::new();
+        self.root.query(cycle, cycle as f64, 1.0, &mut events);
+        events.sort_by(|a, b| a.start.total_cmp(&b.start));
+        events
+    }
+
+    /// Events of the first `cycles` cycles.
+    pub fn events(&self, cycles: usize) -> Vec<Event> {
+        (0..cycles as i64).flat_map(|cycle| self.query(cycle)).collect()
+    }
+
+    /// Timeline of the first `cycles` cycles, with keys tuned by `tuning`.
+    pub fn timeline(&self, tempo: &TempoMap, tuning: &Tuning, cycles: usize, velocity: f32, instrument: usize) -> Result<Timeline> {
+        to_timeline(&self.events(cycles), tempo, tuning, velocity, instrument)
+    }
+
+    /// Note-on/note-off events of the first `cycles` cycles for a `VoiceAllocator`.
+    pub fn voice_events(&self, tempo: &TempoMap, tuning: &Tuning, sample_rate: u32, cycles: usize, velocity: f32) -> Result<Vec<VoiceEvent>> {
+        let frame = |cycle: f64| tempo.sample_at(cycle, sample_rate).round() as usize;
+        let mut events = Vec::new();
+        for event in self.events(cycles) {
+            let frequency = tuning.frequency(event.key)?;
+            let on = frame(event.start);
+            events.push(VoiceEvent { frame: on, kind: EventKind::NoteOn { key: event.key, frequency, velocity } });
+            // At least one frame long, so the note-off cannot sort ahead of its own note-on.
+            events.push(VoiceEvent { frame: frame(event.start + event.duration).max(on + 1), kind: EventKind::NoteOff { key: event.key } });
+        }
+        // Note-offs go first so a key repeated on consecutive steps retriggers.
+        events.sort_by_key(|e| (e.frame, matches!(e.kind, EventKind::NoteOn { .. })));
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(pattern: &str, cycle: i64) -> Vec<(f64, f64, u8)> {
+        Pattern::parse(pattern).unwrap().query(cycle).into_iter().map(|e| (e.start, e.duration, e.key)).collect()
+    }
+
+    #[test]
+    fn test_subdivision_rests_and_alternation() {
+        let pattern = "c4 e4 [g4 b4] ~ <a3 f3>";
+        assert_eq!(keys(pattern, 0), vec![(0.0, 0.2, 60), (0.2, 0.2, 64), (0.4, 0.1, 67), (0.5, 0.1, 71), (0.8, 0.2, 57)]);
+        assert_eq!(keys(pattern, 1)[4], (1.8, 0.2, 53));
+        assert_eq!(keys(pattern, 2)[4].2, 57);
+        assert_eq!(keys("<60 <62 64>>", 3), vec![(3.0, 1.0, 64)]);
+        assert_eq!(keys("[60, 64 67]", 0), vec![(0.0, 1.0, 60), (0.0, 0.5, 64), (0.5, 0.5, 67)]);
+    }
+
+    #[test]
+    fn test_repetition() {
+        assert_eq!(keys("60*2 62", 0), vec![(0.0, 0.25, 60), (0.25, 0.25, 60), (0.5, 0.5, 62)]);
+        assert_eq!(keys("60!3 62", 0), keys("60 60 60 62", 0));
+        assert_eq!(keys("60 ! ! 62", 0), keys("60 60 60 62", 0));
+        assert_eq!(keys("<60 62>*2", 5), vec![(5.0, 0.5, 60), (5.5, 0.5, 62)]);
+    }
+
+    #[test]
+    fn test_euclid() {
+        assert_eq!(euclid(3, 8), vec![true, false, false, true, false, false, true, false]);
+        assert_eq!(euclid(5, 8), vec![true, false, true, true, false, true, true, false]);
+        assert_eq!(euclid(0, 4), vec![false; 4]);
+        assert_eq!(euclid(4, 4), vec![true; 4]);
+        let starts: Vec<f64> = keys("36(3,8)", 0).iter().map(|e| e.0).collect();
+        assert_eq!(starts, vec![0.0, 0.375, 0.75]);
+        let rotated: Vec<f64> = keys("36(3,8,2)", 0).iter().map(|e| e.0).collect();
+        assert_eq!(rotated, vec![0.125, 0.5, 0.75]);
+    }
+
+    #[test]
+    fn test_errors() {
+        for bad in ["[c4 e4", "c4 ]", "h4", "! c4", "c4*0", "c4(9,8)", "[]", "<a3, b3>"] {
+            assert!(Pattern::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_voice_events() {
+        let tempo = TempoMap::new(2.0, 4.0).unwrap();
+        let events = Pattern::parse("60 60").unwrap().voice_events(&tempo, &Tuning::default(), 1000, 1, 1.0).unwrap();
+        let frames: Vec<usize> = events.iter().map(|e| e.frame).collect();
+        assert_eq!(frames, vec![0, 250, 250, 500]);
+        assert!(matches!(events[1].kind, EventKind::NoteOff { .. }));
+    }
+
+    #[test]
+    fn test_sub_frame_events_are_released() {
+        let tempo = TempoMap::new(2.0, 4.0).unwrap();
+        // Half-frame subdivisions at 1000 Hz.
+        let events = Pattern::parse("[60 62]*500").unwrap().voice_events(&tempo, &Tuning::default(), 1000, 1, 1.0).unwrap();
+        let mut held = std::collections::HashSet::new();
+        for event in &events {
+            match event.kind {
+                EventKind::NoteOn { key, .. } => held.insert(key),
+                EventKind::NoteOff { key } => held.remove(&key),
+            };
+        }
+        assert!(held.is_empty(), "{:?}", held);
+    }
+}
@@ -0,0 +1,238 @@
+//! Portamento and per-note pitch envelopes.
+//! Notes are rendered through a single `Oscillator`, so phase carries across
+//! note boundaries and glides.
+
+use crate::envelope::Shape;
+use crate::error::{Error, Result};
+use crate::oscillator::Oscillator;
+use crate::render::PhaseUgen;
+use crate::synth_config::SynthConfig;
+
+/// How long a glide takes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlideMode {
+    /// Every glide lasts this many seconds.
+    Time(f32),
+    /// Glides move at this many semitones per second.
+    Rate(f32),
+}
+
+/// Path of a glide between two frequencies.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlideCurve {
+    /// Straight line in Hz.
+    Linear,
+    /// Straight line in pitch, i.e. exponential in Hz.
+    Exponential,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glide {
+    pub mode: GlideMode,
+    pub curve: GlideCurve,
+}
+
+impl Glide {
+    pub fn new(mode: GlideMode, curve: GlideCurve) -> Result<Glide> {
+        let glide = Glide { mode, curve };
+        glide.check()?;
+        Ok(glide)
+    }
+
+    fn check(&self) -> Result<()> {
+        match self.mode {
+            GlideMode::Time(seconds) if !seconds.is_finite() || seconds < 0.0 => {
+                Err(Error::invalid("Glide time must be finite and non-negative"))
+            }
+            GlideMode::Rate(rate) if !rate.is_finite() || rate <= 0.0 => {
+                Err(Error::invalid("Glide rate must be finite and positive"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether glides work in pitch and so cannot start or end at 0 Hz.
+    fn is_logarithmic(&self) -> bool {
+        self.curve == GlideCurve::Exponential || matches!(self.mode, GlideMode::Rate(_))
+    }
+
+    /// Duration in seconds of a glide from `from` to `to` Hz.
+    pub fn duration(&self, from: f32, to: f32) -> f32 {
+        match self.mode {
+            GlideMode::Time(seconds) => seconds.max(0.0),
+            GlideMode::Rate(semitones_per_second) => {
+                let semitones = (12.0 * (to / from).log2()).abs();
+                semitones / semitones_per_second
+            }
+        }
+    }
+
+    /// Frequency `t` seconds into a glide from `from` to `to` Hz.
+    pub fn frequency_at(&self, from: f32, to: f32, t: f32) -> f32 {
+        let duration = self.duration(from, to);
+        if duration <= 0.0 || t >= duration {
+            return to;
+        }
+        let x = (t / duration).max(0.0);
+        match self.curve {
+            GlideCurve::Linear => from + (to - from) * x,
+            GlideCurve::Exponential => from * (to / from).powf(x),
+        }
+    }
+}
+
+/// Pitch offset driven by an envelope shape, scaled to `depth` semitones.
+#[derive(Clone, Debug)]
+pub struct PitchEnvelope {
+    pub shape: Shape,
+    pub depth: f32,
+}
+
+impl PitchEnvelope {
+    pub fn new(shape: Shape, depth: f32) -> PitchEnvelope {
+        PitchEnvelope { shape, depth }
+    }
+
+    /// Frequency multiplier at sample `i` of the note.
+    pub fn ratio_at(&self, i: usize) -> f32 {
+        2f32.powf(self.depth * self.shape.value_at(i) / 12.0)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PitchedNote {
+    pub frequency: f32,
+    /// Duration in seconds.
+    pub duration: f32,
+    pub pitch_envelope: Option<PitchEnvelope>,
+}
+
+impl PitchedNote {
+    pub fn new(frequency: f32, duration: f32) -> PitchedNote {
+        PitchedNote { frequency, duration, pitch_envelope: None }
+    }
+
+    pub fn with_pitch_envelope(mut self, envelope: PitchEnvelope) -> PitchedNote {
+        self.pitch_envelope = Some(envelope);
+        self
+    }
+}
+
+/// Instantaneous frequency of every sample in a legato line.
+/// Each note glides from the pitch the previous note actually reached when
+/// `glide` is set, so notes shorter than the glide never jump.
+pub fn frequency_track(config: &SynthConfig, notes: &[PitchedNote], glide: Option<Glide>) -> Result<Vec<f32>> {
+    if let Some(glide) = &glide {
+        glide.check()?;
+    }
+    for note in notes {
+        if !note.frequency.is_finite() || note.frequency < 0.0 || !note.duration.is_finite() || note.duration < 0.0 {
+            return Err(Error::invalid("Notes need finite, non-negative frequency and duration"));
+        }
+        if note.frequency == 0.0 && glide.is_some_and(|g| g.is_logarithmic()) {
+            return Err(Error::invalid("Exponential and rate-based glides cannot reach 0 Hz"));
+        }
+    }
+    let sr = config.sample_rate as f32;
+    let mut freqs = Vec::new();
+    let mut previous: Option<f32> = None;
+    for note in notes {
+        let num_samples = (note.duration * sr).round() as usize;
+        let base_at = |t: f32| match (glide, previous) {
+            (Some(glide), Some(from)) => glide.frequency_at(from, note.frequency, t),
+            _ => note.frequency,
+        };
+        for i in 0..num_samples {
+            let ratio = note.pitch_envelope.as_ref().map_or(1.0, |env| env.ratio_at(i));
+            freqs.push(base_at(i as f32 / sr) * ratio);
+        }
+        previous = Some(base_at(num_samples as f32 / sr));
+    }
+    Ok(freqs)
+}
+
+/// Renders a legato line through one oscillator so phase is never reset.
+pub fn render_legato(config: &SynthConfig, ugen: PhaseUgen, notes: &[PitchedNote], glide: Option<Glide>) -> Result<Vec<f32>> {
+    let freqs = frequency_track(config, notes, glide)?;
+    let mut samples = vec![0.0; freqs.len()];
+    Oscillator::new(ugen, Some(0.5)).fill(config, &freqs, &mut samples);
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::{Curve, Envelope};
+    use crate::time_forms;
+
+    fn test_config() -> SynthConfig {
+        SynthConfig::new(1000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0).unwrap()
+    }
+
+    #[test]
+    fn test_glide_duration() {
+        let glide = Glide::new(GlideMode::Rate(12.0), GlideCurve::Exponential).unwrap();
+        assert!((glide.duration(220.0, 440.0) - 1.0).abs() < 1e-6);
+        let glide = Glide::new(GlideMode::Time(0.25), GlideCurve::Linear).unwrap();
+        assert_eq!(glide.duration(220.0, 440.0), 0.25);
+        assert_eq!(glide.frequency_at(200.0, 400.0, 0.125), 300.0);
+        assert_eq!(glide.frequency_at(200.0, 400.0, 1.0), 400.0);
+    }
+
+    #[test]
+    fn test_exponential_glide_midpoint() {
+        let glide = Glide::new(GlideMode::Time(1.0), GlideCurve::Exponential).unwrap();
+        let mid = glide.frequency_at(220.0, 880.0, 0.5);
+        assert!((mid - 440.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_phase_continuity() {
+        let config = test_config();
+        let notes = vec![PitchedNote::new(50.0, 0.013), PitchedNote::new(75.0, 0.1)];
+        let samples = render_legato(&config, time_forms::sine_phase, &notes, None).unwrap();
+        // The largest step a 75 Hz sine can take in one sample at 1 kHz.
+        let max_step = 2.0 * std::f32::consts::PI * 75.0 / 1000.0;
+        for pair in samples.windows(2) {
+            assert!((pair[1] - pair[0]).abs() <= max_step + 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_pitch_envelope() {
+        let config = test_config();
+        let envelope = Envelope::new(100, 1, 1.0, false).unwrap();
+        let drop = PitchEnvelope::new(Shape::new(&envelope, Curve::Linear { slope: 1.0 }).unwrap().reverse(), 12.0);
+        let notes = vec![PitchedNote::new(100.0, 0.2).with_pitch_envelope(drop)];
+        let freqs = frequency_track(&config, &notes, None).unwrap();
+        assert_eq!(freqs.len(), 200);
+        assert!((freqs[0] - 200.0).abs() < 1e-3);
+        assert!((freqs[150] - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_interrupted_glide() {
+        let config = test_config();
+        let glide = Glide::new(GlideMode::Time(1.0), GlideCurve::Linear).unwrap();
+        let notes = vec![PitchedNote::new(200.0, 0.1), PitchedNote::new(400.0, 0.1), PitchedNote::new(200.0, 0.1)];
+        let freqs = frequency_track(&config, &notes, Some(glide)).unwrap();
+        // The second note only gets 20 Hz up its glide before the third starts from there.
+        assert!((freqs[199] - 219.8).abs() < 1e-3);
+        assert!((freqs[200] - 220.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_invalid_glides() {
+        assert!(Glide::new(GlideMode::Rate(0.0), GlideCurve::Linear).is_err());
+        assert!(Glide::new(GlideMode::Time(f32::NAN), GlideCurve::Linear).is_err());
+        assert!(Glide::new(GlideMode::Time(-1.0), GlideCurve::Linear).is_err());
+        let config = test_config();
+        let exponential = Glide::new(GlideMode::Time(0.1), GlideCurve::Exponential).unwrap();
+        let notes = vec![PitchedNote::new(0.0, 0.1), PitchedNote::new(100.0, 0.1)];
+        assert!(frequency_track(&config, &notes, Some(exponential)).is_err());
+        let linear = Glide::new(GlideMode::Time(0.1), GlideCurve::Linear).unwrap();
+        assert!(frequency_track(&config, &notes, Some(linear)).is_ok());
+        let rate = Glide { mode: GlideMode::Rate(0.0), curve: GlideCurve::Linear };
+        assert!(frequency_track(&config, &notes[1..], Some(rate)).is_err());
+    }
+}
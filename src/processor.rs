@@ -0,0 +1,141 @@
+//! Host-style processing interface.
+//! `AudioProcessor` mirrors how plugin hosts drive audio code: a `prepare`
+//! call with the stream format, then repeated `process` calls on short
+//! per-channel blocks. `NullDriver` plays the host offline.
+
+use crate::buffer::AudioBuffer;
+use crate::error::{Error, Result};
+
+pub trait AudioProcessor {
+    /// Called before processing starts or whenever the stream format changes.
+    fn prepare(&mut self, sample_rate: u32, max_block_size: usize);
+
+    /// Processes one block in place. Every channel slice has the same length,
+    /// which never exceeds the `max_block_size` given to `prepare`.
+    fn process(&mut self, channels: &mut [&mut [f32]]);
+
+    /// Clears internal state such as phases, envelopes and delay lines.
+    fn reset(&mut self);
+}
+
+/// Offline driver that calls a processor in real-time sized blocks.
+pub struct NullDriver {
+    sample_rate: u32,
+    block_size: usize,
+    channels: u16,
+}
+
+impl NullDriver {
+    pub fn new(sample_rate: u32, block_size: usize, channels: u16) -> Result<NullDriver> {
+        if sample_rate == 0 || block_size == 0 || channels == 0 {
+            return Err(Error::invalid("Sample rate, block size and channel count must be positive"));
+        }
+        Ok(NullDriver { sample_rate, block_size, channels })
+    }
+
+    /// Runs `processor` as a generator for `frames` frames, starting from silence.
+    pub fn run(&self, processor: &mut dyn AudioProcessor, frames: usize) -> Result<AudioBuffer> {
+        let input = AudioBuffer::new(self.channels, self.sample_rate, frames)?;
+        self.process_buffer(processor, &input)
+    }
+
+    /// Runs `input` through `processor` block by block and returns the result.
+    pub fn process_buffer(&self, processor: &mut dyn AudioProcessor, input: &AudioBuffer) -> Result<AudioBuffer> {
+        if input.channels != self.channels || input.sample_rate != self.sample_rate {
+            return Err(Error::invalid("Input buffer does not match the driver's format"));
+        }
+        let c = self.channels as usize;
+        let mut output = input.clone();
+        let mut scratch = vec![vec![0.0; self.block_size]; c];
+        processor.prepare(self.sample_rate, self.block_size);
+        for block in output.samples.chunks_mut(self.block_size * c) {
+            let frames = block.len() / c;
+            for (i, frame) in block.chunks_exact(c).enumerate() {
+                for (channel, &x) in frame.iter().enumerate() {
+                    scratch[channel][i] = x;
+                }
+            }
+            let mut slices: Vec<&mut [f32]> = scratch.iter_mut().map(|ch| &mut ch[..frames]).collect();
+            processor.process(&mut slices);
+            for (i, frame) in block.chunks_exact_mut(c).enumerate() {
+                for (channel, x) in frame.iter_mut().enumerate() {
+                    *x = scratch[channel][i];
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ramp generator that records how it was driven.
+    struct Ramp {
+        value: f32,
+        max_block_size: usize,
+        calls: usize,
+    }
+
+    impl AudioProcessor for Ramp {
+        fn prepare(&mut self, _sample_rate: u32, max_block_size: usize) {
+            self.max_block_size = max_block_size;
+        }
+
+        fn process(&mut self, channels: &mut [&mut [f32]]) {
+            assert!(channels[0].len() <= self.max_block_size);
+            self.calls += 1;
+            for i in 0..channels[0].len() {
+                for channel in channels.iter_mut() {
+                    channel[i] = self.value;
+                }
+                self.value += 1.0;
+            }
+        }
+
+        fn reset(&mut self) {
+            self.value = 0.0;
+        }
+    }
+
+    struct Gain(f32);
+
+    impl AudioProcessor for Gain {
+        fn prepare(&mut self, _sample_rate: u32, _max_block_size: usize) {}
+
+        fn process(&mut self, channels: &mut [&mut [f32]]) {
+            for channel in channels.iter_mut() {
+                channel.iter_mut().for_each(|x| *x *= self.0);
+            }
+        }
+
+        fn reset(&mut self) {}
+    }
+
+    #[test]
+    fn test_generator_blocks() {
+        let driver = NullDriver::new(48000, 64, 2).unwrap();
+        let mut ramp = Ramp { value: 0.0, max_block_size: 0, calls: 0 };
+        let output = driver.run(&mut ramp, 150).unwrap();
+        assert_eq!(ramp.calls, 3);
+        assert_eq!(output.frames(), 150);
+        assert_eq!(output.channel(0), (0..150).map(|i| i as f32).collect::<Vec<f32>>());
+        assert_eq!(output.channel(1), output.channel(0));
+
+        ramp.reset();
+        let again = driver.run(&mut ramp, 150).unwrap();
+        assert_eq!(again, output);
+    }
+
+    #[test]
+    fn test_effect_on_input() {
+        let driver = NullDriver::new(8000, 16, 1).unwrap();
+        let input = AudioBuffer::from_mono(vec![0.5; 40], 8000);
+        let output = driver.process_buffer(&mut Gain(0.5), &input).unwrap();
+        assert_eq!(output.samples, vec![0.25; 40]);
+
+        let wrong_rate = AudioBuffer::from_mono(vec![0.5; 40], 44100);
+        assert!(driver.process_buffer(&mut Gain(0.5), &wrong_rate).is_err());
+    }
+}
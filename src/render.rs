@@ -1,28 +1,61 @@
+use crate::buffer::AudioBuffer;
+use crate::error::{Error, Result};
+use crate::pan::PanLaw;
 use crate::synth_config::SynthConfig;
 
 pub type Ugen = fn(&SynthConfig, u32, f32, Option<f32>) -> f32;
 
-pub fn render_ugen(config: &SynthConfig, ugen: &Ugen, filename: &str) -> String {
-    let dur_cycles = 4;
-    let spec = hound::WavSpec {
-        channels: 1,
-        sample_rate: config.sample_rate,
-        bits_per_sample: 32,
-        sample_format: hound::SampleFormat::Float,
-    };
-    let mut writer = hound::WavWriter::create(filename, spec).unwrap();
-    let mut ts: Vec<u32> = Vec::new();
+/// Waveform evaluated at a phase in cycles rather than at a sample index.
+/// The frequency argument is only used for band-limiting.
+pub type PhaseUgen = fn(&SynthConfig, f32, f32, Option<f32>) -> f32;
 
-    for i in 0..(dur_cycles * config.sample_rate) { 
-        ts.push(i) 
-    };
+/// Parameters for rendering a single ugen note.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderParams {
+    /// Duration in seconds.
+    pub duration: f32,
+    pub freq: f32,
+    pub amp: f32,
+    pub bias: Option<f32>,
+}
+
+impl RenderParams {
+    pub fn new(duration: f32, freq: f32, amp: f32) -> RenderParams {
+        RenderParams { duration, freq, amp, bias: Some(0.5) }
+    }
+
+    pub fn with_bias(mut self, bias: Option<f32>) -> RenderParams {
+        self.bias = bias;
+        self
+    }
+
+    pub fn num_samples(&self, config: &SynthConfig) -> u32 {
+        (self.duration * config.sample_rate as f32).round() as u32
+    }
+}
+
+impl Default for RenderParams {
+    fn default() -> RenderParams {
+        RenderParams::new(4.0, 440.0, 1.0)
+    }
+}
 
-    let sequence = render2(config, ts, config.sample_rate, ugen, 440.0, 1.0);
-    for sample in sequence {
-        writer.write_sample(sample).unwrap();
+/// Renders a ugen into a mono in-memory buffer.
+pub fn render(config: &SynthConfig, ugen: &Ugen, params: &RenderParams) -> Result<AudioBuffer> {
+    if params.duration < 0.0 {
+        return Err(Error::invalid("Duration must not be negative"));
     }
-    writer.finalize().unwrap();
-    String::from("done")
+    config.check_frequency(params.freq)?;
+    let samples = (0..params.num_samples(config))
+        .map(|t| params.amp * ugen(config, t, params.freq, params.bias))
+        .collect();
+    Ok(AudioBuffer::from_mono(samples, config.sample_rate))
+}
+
+/// Renders the default test tone and writes it to `filename`, returning the path.
+pub fn render_ugen(config: &SynthConfig, ugen: &Ugen, filename: &str) -> Result<String> {
+    render(config, ugen, &RenderParams::default())?.write_wav(filename)?;
+    Ok(String::from(filename))
 }
 
 pub fn render2(config: &SynthConfig, ts: Vec<u32>, sr:u32, ugen: &Ugen, freq: f32, amp: f32) -> Vec<f32> {
@@ -34,3 +67,49 @@ pub fn render2(config: &SynthConfig, ts: Vec<u32>, sr:u32, ugen: &Ugen, freq: f3
     samples
 }
 
+/// A mono ugen voice placed in the stereo field.
+pub struct PannedSource {
+    pub ugen: Ugen,
+    pub freq: f32,
+    pub amp: f32,
+    /// Position in `[-1, 1]`, where -1 is hard left.
+    pub pan: f32,
+}
+
+/// Renders `num_frames` frames of every source into one stereo buffer.
+pub fn render_stereo(config: &SynthConfig, sources: &[PannedSource], law: PanLaw, num_frames: u32) -> Result<AudioBuffer> {
+    let mut buffer = AudioBuffer::new(2, config.sample_rate, num_frames as usize)?;
+    for source in sources {
+        config.check_frequency(source.freq)?;
+        let samples = render2(config, (0..num_frames).collect(), config.sample_rate, &source.ugen, source.freq, source.amp);
+        buffer.add_panned(0, &samples, source.pan, law)?;
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_forms;
+
+    #[test]
+    fn test_render_in_memory() {
+        let config = SynthConfig::new(8000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0).unwrap();
+        let params = RenderParams::new(0.5, 100.0, 0.25);
+        let buffer = render(&config, &(time_forms::sine as Ugen), &params).unwrap();
+        assert_eq!(buffer.channels, 1);
+        assert_eq!(buffer.sample_rate, 8000);
+        assert_eq!(buffer.frames(), 4000);
+        let peak = buffer.samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!((peak - 0.25).abs() < 1e-3);
+        assert_eq!(buffer.samples, render2(&config, (0..4000).collect(), 8000, &(time_forms::sine as Ugen), 100.0, 0.25));
+    }
+
+    #[test]
+    fn test_render_rejects_out_of_range_frequency() {
+        let config = SynthConfig::new(8000, 20.0, 2000.0, 1.0, 0.0, 0.0, 1.0).unwrap();
+        let params = RenderParams::new(0.5, 3000.0, 0.25);
+        let result = render(&config, &(time_forms::sine as Ugen), &params);
+        assert!(matches!(result, Err(Error::FrequencyOutOfRange(f)) if f == 3000.0));
+    }
+}
@@ -0,0 +1,233 @@
+//! Sample-rate conversion with polyphase windowed-sinc filters.
+//! The filter table holds a fixed number of phases per input sample and
+//! intermediate phases are interpolated, so any pair of rates is supported.
+
+use std::f64::consts::PI;
+use crate::buffer::AudioBuffer;
+use crate::error::{Error, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quality {
+    Fast,
+    Medium,
+    High,
+}
+
+impl Quality {
+    /// Zero crossings on each side of the kernel center, and table phases per sample.
+    fn design(&self) -> (usize, usize) {
+        match self {
+            Quality::Fast => (8, 64),
+            Quality::Medium => (16, 256),
+            Quality::High => (32, 1024),
+        }
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) }
+}
+
+fn blackman(x: f64) -> f64 {
+    0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+}
+
+/// Streaming converter for interleaved audio.
+pub struct Resampler {
+    channels: usize,
+    from: u32,
+    to: u32,
+    /// Kernel half-width in input samples.
+    half: usize,
+    phases: usize,
+    kernel: Vec<f32>,
+    /// Per-channel input not yet fully consumed.
+    history: Vec<Vec<f32>>,
+    /// Integer and fractional (in units of `1 / to`) read position into `history`.
+    pos: usize,
+    frac: u32,
+    input_frames: u64,
+    output_frames: u64,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32, channels: u16, quality: Quality) -> Result<Resampler> {
+        if from == 0 || to == 0 {
+            return Err(Error::invalid("Sample rates must be positive"));
+        }
+        if channels == 0 {
+            return Err(Error::invalid("Resampler needs at least one channel"));
+        }
+        let g = gcd(from, to);
+        let (from, to) = (from / g, to / g);
+        let (zero_crossings, phases) = quality.design();
+        // Lower the cutoff below the output Nyquist when decimating.
+        let cutoff = (to as f64 / from as f64).min(1.0) * 0.97;
+        let half = (zero_crossings as f64 / cutoff).ceil() as usize;
+        let len = 2 * half * phases + 1;
+        let kernel = (0..len)
+            .map(|i| {
+                let x = i as f64 / phases as f64 - half as f64;
+                (cutoff * sinc(cutoff * x) * blackman(x / half as f64)) as f32
+            })
+            .collect();
+        Ok(Resampler {
+            channels: channels as usize,
+            from,
+            to,
+            half,
+            phases,
+            kernel,
+            history: vec![vec![0.0; half]; channels as usize],
+            pos: half,
+            frac: 0,
+            input_frames: 0,
+            output_frames: 0,
+        })
+    }
+
+    /// Output frames expected for `frames` input frames.
+    pub fn output_len(&self, frames: u64) -> u64 {
+        (frames * self.to as u64).div_ceil(self.from as u64)
+    }
+
+    pub fn reset(&mut self) {
+        for channel in self.history.iter_mut() {
+            channel.clear();
+            channel.resize(self.half, 0.0);
+        }
+        self.pos = self.half;
+        self.frac = 0;
+        self.input_frames = 0;
+        self.output_frames = 0;
+    }
+
+    /// Kernel value at `x` input samples from the center, interpolating between phases.
+    fn tap(&self, x: f64) -> f32 {
+        let index = (x + self.half as f64) * self.phases as f64;
+        let i = index.floor();
+        let t = (index - i) as f32;
+        let i = i as usize;
+        let a = self.kernel.get(i).copied().unwrap_or(0.0);
+        let b = self.kernel.get(i + 1).copied().unwrap_or(0.0);
+        a + (b - a) * t
+    }
+
+    fn produce(&mut self, out: &mut Vec<f32>) {
+        let available = self.history[0].len();
+        while self.pos + self.half < available {
+            let offset = self.frac as f64 / self.to as f64;
+            let start = self.pos + 1 - self.half;
+            for channel in 0..self.channels {
+                let mut acc = 0.0;
+                for k in 0..2 * self.half {
+                    let x = (start + k) as f64 - (self.pos as f64 + offset);
+                    acc += self.history[channel][start + k] * self.tap(x);
+                }
+                out.push(acc);
+            }
+            self.output_frames += 1;
+            self.frac += self.from;
+            self.pos += (self.frac / self.to) as usize;
+            self.frac %= self.to;
+        }
+        // Keep only what the next output still needs.
+        let keep_from = (self.pos + 1).saturating_sub(self.half).min(available);
+        if keep_from > 0 {
+            for channel in self.history.iter_mut() {
+                channel.drain(..keep_from);
+            }
+            self.pos -= keep_from;
+        }
+    }
+
+    /// Converts a block of interleaved input, returning whatever output is ready.
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        for frame in input.chunks_exact(self.channels) {
+            for (channel, &x) in frame.iter().enumerate() {
+                self.history[channel].push(x);
+            }
+        }
+        self.input_frames += (input.len() / self.channels) as u64;
+        let mut out = Vec::new();
+        self.produce(&mut out);
+        out
+    }
+
+    /// Drains the filter tail so the total output matches the input duration.
+    pub fn flush(&mut self) -> Vec<f32> {
+        for channel in self.history.iter_mut() {
+            channel.extend(std::iter::repeat_n(0.0, self.half + 1));
+        }
+        let mut out = Vec::new();
+        self.produce(&mut out);
+        let expected = self.output_len(self.input_frames);
+        let extra = self.output_frames.saturating_sub(expected) as usize;
+        out.truncate(out.len() - extra.min(out.len() / self.channels) * self.channels);
+        self.output_frames -= extra as u64;
+        out
+    }
+}
+
+/// Converts a whole buffer to `sample_rate`.
+pub fn resample(buffer: &AudioBuffer, sample_rate: u32, quality: Quality) -> Result<AudioBuffer> {
+    if sample_rate == buffer.sample_rate {
+        return Ok(buffer.clone());
+    }
+    let mut resampler = Resampler::new(buffer.sample_rate, sample_rate, buffer.channels, quality)?;
+    let mut samples = resampler.process(&buffer.samples);
+    samples.extend(resampler.flush());
+    Ok(AudioBuffer { samples, channels: buffer.channels, sample_rate })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, sample_rate: u32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_44100_to_48000() {
+        let input = AudioBuffer::from_mono(sine(1000.0, 44100, 4410), 44100);
+        let output = resample(&input, 48000, Quality::High).unwrap();
+        assert_eq!(output.frames(), 4800);
+        let expected = sine(1000.0, 48000, 4800);
+        for (i, (&actual, &ideal)) in output.samples.iter().zip(&expected).enumerate().take(4600).skip(200) {
+            assert!((actual - ideal).abs() < 2e-3, "{}: {} {}", i, actual, ideal);
+        }
+    }
+
+    #[test]
+    fn test_downsample_removes_aliases() {
+        let input = AudioBuffer::from_mono(sine(30000.0, 96000, 9600), 96000);
+        let output = resample(&input, 44100, Quality::Medium).unwrap();
+        assert_eq!(output.frames(), 4410);
+        let peak = output.samples[500..3900].iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!(peak < 0.01, "{}", peak);
+    }
+
+    #[test]
+    fn test_streaming_matches_whole_buffer() {
+        let left = sine(440.0, 48000, 3000);
+        let right = sine(660.0, 48000, 3000);
+        let input = AudioBuffer::from_channels(&[left, right], 48000).unwrap();
+        let whole = resample(&input, 44100, Quality::Fast).unwrap();
+
+        let mut resampler = Resampler::new(48000, 44100, 2, Quality::Fast).unwrap();
+        let mut streamed = Vec::new();
+        for block in input.samples.chunks(2 * 97) {
+            streamed.extend(resampler.process(block));
+        }
+        streamed.extend(resampler.flush());
+        assert_eq!(streamed, whole.samples);
+        assert_eq!(whole.channels, 2);
+    }
+}
@@ -1,15 +1,179 @@
+//! Note events and the timeline renderer that mixes them.
+
+use crate::buffer::AudioBuffer;
+use crate::error::{Error, Result};
+use crate::render::PhaseUgen;
 use crate::synth_config::SynthConfig;
 
+/// One empty buffer per rendering thread.
 pub fn allocate_buffers(num_threads: usize, config: &SynthConfig) -> Vec<Vec<f32>> {
     (0..num_threads).map(|_| Vec::new()).collect()
 }
 
+/// Joins per-thread segments back to back, in thread order.
 pub fn merge_buffers(buffers: Vec<Vec<f32>>) -> Vec<f32> {
     buffers.into_iter().flatten().collect()
 }
 
-pub enum ErrorType {
-    InvalidInput,
+#[derive(Clone, Debug, PartialEq)]
+pub struct Note {
+    /// Start time in seconds; fractional sample positions are honored.
+    pub start: f64,
+    /// Seconds from note-on to note-off.
+    pub duration: f64,
+    pub frequency: f32,
+    /// Velocity in `[0, 1]`.
+    pub velocity: f32,
+    /// Index into the instruments passed to the renderer.
+    pub instrument: usize,
+}
+
+impl Note {
+    pub fn new(start: f64, duration: f64, frequency: f32, velocity: f32, instrument: usize) -> Note {
+        Note { start, duration, frequency, velocity, instrument }
+    }
+}
+
+/// Sound source for timeline notes.
+/// Samples are a pure function of time since note-on, so any stretch of a note
+/// can be rendered independently of the rest.
+pub trait Instrument: Sync {
+    /// Seconds a note keeps sounding after note-off.
+    fn release(&self) -> f64;
+
+    /// Value `t` seconds after the note started.
+    fn sample(&self, config: &SynthConfig, note: &Note, t: f64) -> f32;
+}
+
+/// Phase ugen shaped by a linear attack/release envelope and scaled by velocity.
+pub struct UgenInstrument {
+    pub ugen: PhaseUgen,
+    pub bias: Option<f32>,
+    /// Attack time in seconds.
+    pub attack: f64,
+    /// Release time in seconds.
+    pub release: f64,
+}
+
+impl UgenInstrument {
+    pub fn new(ugen: PhaseUgen, attack: f64, release: f64) -> UgenInstrument {
+        UgenInstrument { ugen, bias: Some(0.5), attack, release }
+    }
+
+    fn level(&self, t: f64, duration: f64) -> f64 {
+        let rise = |t: f64| if self.attack > 0.0 { (t / self.attack).min(1.0) } else { 1.0 };
+        if t < duration {
+            rise(t)
+        } else if self.release > 0.0 {
+            rise(duration) * (1.0 - (t - duration) / self.release).max(0.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Instrument for UgenInstrument {
+    fn release(&self) -> f64 {
+        self.release
+    }
+
+    fn sample(&self, config: &SynthConfig, note: &Note, t: f64) -> f32 {
+        let freq = note.frequency + config.tuning_offset_hz;
+        let phase = (t * freq as f64).rem_euclid(1.0) as f32;
+        let level = self.level(t, note.duration) as f32;
+        note.velocity * level * (self.ugen)(config, phase, freq, self.bias)
+    }
+}
+
+/// Notes on an absolute time axis, mixed together when rendered.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timeline {
+    pub notes: Vec<Note>,
+}
+
+impl Timeline {
+    pub fn new(notes: Vec<Note>) -> Timeline {
+        Timeline { notes }
+    }
+
+    pub fn push(&mut self, note: Note) {
+        self.notes.push(note);
+    }
+
+    fn check(&self, config: &SynthConfig, instruments: &[&dyn Instrument]) -> Result<()> {
+        for note in &self.notes {
+            if note.instrument >= instruments.len() {
+                return Err(Error::invalid(format!("No instrument {} for note at {}s", note.instrument, note.start)));
+            }
+            if !note.start.is_finite() || note.start < 0.0 || !note.duration.is_finite() || note.duration < 0.0 {
+                return Err(Error::invalid("Notes must have finite, non-negative start and duration"));
+            }
+            config.check_frequency(note.frequency)?;
+        }
+        Ok(())
+    }
+
+    /// Frame span `[first, end)` a note occupies, including its release tail.
+    fn frames(config: &SynthConfig, note: &Note, release: f64) -> (usize, usize) {
+        let sr = config.sample_rate as f64;
+        let first = (note.start * sr).ceil() as usize;
+        let end = ((note.start + note.duration + release) * sr).ceil() as usize;
+        (first, end)
+    }
+
+    /// Number of frames needed to hold every note and its release.
+    pub fn length(&self, config: &SynthConfig, instruments: &[&dyn Instrument]) -> Result<usize> {
+        self.check(config, instruments)?;
+        Ok(self.notes.iter()
+            .map(|note| Timeline::frames(config, note, instruments[note.instrument].release()).1)
+            .max()
+            .unwrap_or(0))
+    }
+
+    /// Mixes every note overlapping `[start_frame, start_frame + out.len())` into `out`.
+    pub fn render_range(&self, config: &SynthConfig, instruments: &[&dyn Instrument], start_frame: usize, out: &mut [f32]) -> Result<()> {
+        self.check(config, instruments)?;
+        let sr = config.sample_rate as f64;
+        let end_frame = start_frame + out.len();
+        for note in &self.notes {
+            let instrument = instruments[note.instrument];
+            let (first, end) = Timeline::frames(config, note, instrument.release());
+            for frame in first.max(start_frame)..end.min(end_frame) {
+                let t = frame as f64 / sr - note.start;
+                out[frame - start_frame] += instrument.sample(config, note, t);
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the whole timeline to a mono buffer.
+    pub fn render(&self, config: &SynthConfig, instruments: &[&dyn Instrument]) -> Result<AudioBuffer> {
+        let mut samples = vec![0.0; self.length(config, instruments)?];
+        self.render_range(config, instruments, 0, &mut samples)?;
+        Ok(AudioBuffer::from_mono(samples, config.sample_rate))
+    }
+
+    /// Renders like `render`, splitting the timeline into one contiguous time
+    /// segment per thread. Every frame sums the same notes in the same order,
+    /// so the output is bit-identical to single-threaded rendering.
+    pub fn render_parallel(&self, config: &SynthConfig, instruments: &[&dyn Instrument], num_threads: usize) -> Result<AudioBuffer> {
+        if num_threads == 0 {
+            return Err(Error::invalid("Rendering needs at least one thread"));
+        }
+        let length = self.length(config, instruments)?;
+        let segment = length.div_ceil(num_threads);
+        let mut buffers = allocate_buffers(num_threads, config);
+        for (i, buffer) in buffers.iter_mut().enumerate() {
+            buffer.resize(segment.min(length.saturating_sub(i * segment)), 0.0);
+        }
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = buffers.iter_mut().enumerate()
+                .map(|(i, buffer)| scope.spawn(move || self.render_range(config, instruments, i * segment, buffer)))
+                .collect();
+            handles.into_iter().try_for_each(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+        })?;
+        Ok(AudioBuffer::from_mono(merge_buffers(buffers), config.sample_rate))
+    }
 }
 
 #[cfg(test)]
@@ -50,4 +214,81 @@ mod tests {
         assert_eq!(merged_buffer[99], 0.5); // Last element of buffer1
         assert_eq!(merged_buffer[100], 0.3); // First element of buffer2
     }
+
+    /// Constant signal, so mixing can be checked exactly.
+    struct Dc;
+
+    impl Instrument for Dc {
+        fn release(&self) -> f64 {
+            0.5
+        }
+
+        fn sample(&self, config: &SynthConfig, note: &Note, t: f64) -> f32 {
+            if t < note.duration { note.velocity } else { note.velocity / 2.0 }
+        }
+    }
+
+    #[test]
+    fn test_overlapping_notes_mix() {
+        let config = SynthConfig { sample_rate: 10, ..test_config() };
+        let timeline = Timeline::new(vec![
+            Note::new(0.0, 1.0, 100.0, 0.25, 0),
+            Note::new(0.55, 0.2, 100.0, 0.5, 0),
+        ]);
+        let buffer = timeline.render(&config, &[&Dc]).unwrap();
+        assert_eq!(buffer.frames(), 15);
+        // The second note starts between frames 5 and 6, so frame 6 is its first sample.
+        assert_eq!(buffer.samples[5], 0.25);
+        assert_eq!(buffer.samples[6], 0.75);
+        assert_eq!(buffer.samples[7], 0.75);
+        // 0.75 s is note-off for the second note; its release lasts until 1.25 s.
+        assert_eq!(buffer.samples[8], 0.5);
+        assert_eq!(buffer.samples[12], 0.125 + 0.25);
+        assert_eq!(buffer.samples[13], 0.125);
+    }
+
+    #[test]
+    fn test_ugen_instrument_release_tail() {
+        let config = test_config();
+        let instrument = UgenInstrument::new(crate::time_forms::sine_phase, 0.01, 0.2);
+        let timeline = Timeline::new(vec![Note::new(0.25, 0.5, 440.0, 1.0, 0)]);
+        let buffer = timeline.render(&config, &[&instrument]).unwrap();
+        assert_eq!(buffer.frames(), (0.95 * 44100.0f64).ceil() as usize);
+        assert!(buffer.samples[..11025].iter().all(|&x| x == 0.0));
+        let tail = &buffer.samples[(0.9 * 44100.0) as usize..];
+        assert!(tail.iter().any(|&x| x.abs() > 0.1));
+        assert!(tail.iter().all(|&x| x.abs() <= 0.26));
+    }
+
+    #[test]
+    fn test_missing_instrument() {
+        let timeline = Timeline::new(vec![Note::new(0.0, 1.0, 440.0, 1.0, 3)]);
+        assert!(timeline.render(&test_config(), &[&Dc]).is_err());
+    }
+
+    #[test]
+    fn test_non_finite_notes() {
+        for (start, duration) in [(0.0, f64::INFINITY), (f64::NAN, 1.0), (f64::INFINITY, 1.0), (0.0, f64::NAN)] {
+            let timeline = Timeline::new(vec![Note::new(start, duration, 440.0, 1.0, 0)]);
+            assert!(timeline.render(&test_config(), &[&Dc]).is_err(), "{} {}", start, duration);
+        }
+    }
+
+    #[test]
+    fn test_parallel_render_is_bit_identical() {
+        let config = test_config();
+        let lead = UgenInstrument::new(crate::time_forms::sine_phase, 0.01, 0.2);
+        let pad = UgenInstrument::new(crate::freq_forms::sawtooth_phase, 0.1, 0.5);
+        let timeline = Timeline::new((0..12)
+            .map(|i| Note::new(i as f64 * 0.137, 0.4, 220.0 + 37.0 * i as f32, 0.3, i % 2))
+            .collect());
+        let instruments: [&dyn Instrument; 2] = [&lead, &pad];
+        let single = timeline.render(&config, &instruments).unwrap();
+        for threads in [1, 2, 3, 7, 64] {
+            let parallel = timeline.render_parallel(&config, &instruments, threads).unwrap();
+            assert_eq!(parallel.samples, single.samples, "{} threads", threads);
+        }
+        assert!(timeline.render_parallel(&config, &instruments, 0).is_err());
+        assert_eq!(Timeline::default().render_parallel(&config, &instruments, 4).unwrap().frames(), 0);
+    }
 }
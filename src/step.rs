@@ -0,0 +1,307 @@
+//! Step sequencer: a loop of steps with per-step pitch, velocity, gate,
+//! probability, ratchets and slide, plus global swing.
+//!
+//! Steps are clocked in cycles, so a sequencer follows `SynthConfig::cps` or
+//! any tempo map. Probability rolls come from a seeded generator, one roll per
+//! step in order, so the same sequencer always renders the same notes.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::error::{Error, Result};
+use crate::sequence::{Note, Timeline};
+use crate::synth_config::SynthConfig;
+use crate::tempo::TempoMap;
+use crate::tuning::Tuning;
+use crate::voice::{EventKind, VoiceEvent};
+
+/// How far a sliding note reaches into the next step, in steps.
+const SLIDE_OVERLAP: f64 = 0.25;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Step {
+    /// MIDI key, or `None` for a rest.
+    pub key: Option<u8>,
+    /// Velocity in `[0, 1]`.
+    pub velocity: f32,
+    /// Fraction of the step (or of each ratchet) the note sounds, in `(0, 1]`.
+    pub gate: f64,
+    /// Chance in `[0, 1]` that the step plays each time it comes around.
+    pub probability: f64,
+    /// Number of evenly spaced hits within the step.
+    pub ratchets: u32,
+    /// Holds the last hit into the next step so a legato voice ties the notes.
+    pub slide: bool,
+}
+
+impl Step {
+    pub fn new(key: u8) -> Step {
+        Step { key: Some(key), velocity: 1.0, gate: 0.5, probability: 1.0, ratchets: 1, slide: false }
+    }
+
+    pub fn rest() -> Step {
+        Step { key: None, ..Step::new(0) }
+    }
+
+    pub fn with_velocity(self, velocity: f32) -> Step {
+        Step { velocity, ..self }
+    }
+
+    pub fn with_gate(self, gate: f64) -> Step {
+        Step { gate, ..self }
+    }
+
+    pub fn with_probability(self, probability: f64) -> Step {
+        Step { probability, ..self }
+    }
+
+    pub fn with_ratchets(self, ratchets: u32) -> Step {
+        Step { ratchets, ..self }
+    }
+
+    pub fn with_slide(self, slide: bool) -> Step {
+        Step { slide, ..self }
+    }
+}
+
+pub struct StepSequencer {
+    pub steps: Vec<Step>,
+    pub steps_per_cycle: u32,
+    /// Share of each pair of steps taken by the first one: 0.5 is straight,
+    /// about 0.67 is a triplet shuffle.
+    pub swing: f64,
+    pub seed: u64,
+}
+
+impl StepSequencer {
+    pub fn new(steps: Vec<Step>, steps_per_cycle: u32) -> Result<StepSequencer> {
+        let sequencer = StepSequencer { steps, steps_per_cycle, swing: 0.5, seed: 0 };
+        sequencer.check()?;
+        Ok(sequencer)
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.steps.is_empty() || self.steps_per_cycle == 0 {
+            return Err(Error::invalid("A step sequencer needs steps and a positive step rate"));
+        }
+        if !(0.5..1.0).contains(&self.swing) {
+            return Err(Error::invalid("Swing must lie in [0.5, 1)"));
+        }
+        for step in &self.steps {
+            // Written as a positive test so NaN fields fail it.
+            let valid = (0.0..=1.0).contains(&step.velocity)
+                && step.gate > 0.0 && step.gate <= 1.0
+                && (0.0..=1.0).contains(&step.probability)
+                && step.ratchets > 0;
+            if !valid {
+                return Err(Error::invalid(format!("Invalid step {:?}", step)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Swung onset and length of the `n`-th step played, in cycles.
+    /// Steps pair up from the start of each cycle; with an odd step count the
+    /// last step of the cycle has no partner and stays straight.
+    fn span(&self, n: usize) -> (f64, f64) {
+        let steps = self.steps_per_cycle as usize;
+        let length = 1.0 / steps as f64;
+        let (cycle, k) = (n / steps, n % steps);
+        let pair_start = cycle as f64 + (k / 2) as f64 * 2.0 * length;
+        let first = 2.0 * self.swing * length;
+        if k + 1 == steps && k.is_multiple_of(2) {
+            (pair_start, length)
+        } else if k.is_multiple_of(2) {
+            (pair_start, first)
+        } else {
+            (pair_start + first, 2.0 * length - first)
+        }
+    }
+
+    /// Notes of the first `cycles` cycles; `start` and `duration` are in cycles.
+    pub fn notes(&self, tuning: &Tuning, cycles: usize, instrument: usize) -> Result<Vec<Note>> {
+        Ok(self.hits(tuning, cycles, instrument)?.into_iter().map(|(_, note)| note).collect())
+    }
+
+    /// Every note played, paired with its key.
+    fn hits(&self, tuning: &Tuning, cycles: usize, instrument: usize) -> Result<Vec<(u8, Note)>> {
+        self.check()?;
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut notes = Vec::new();
+        for n in 0..cycles * self.steps_per_cycle as usize {
+            let step = &self.steps[n % self.steps.len()];
+            let roll: f64 = rng.gen();
+            let key = match step.key {
+                Some(key) if roll < step.probability => key,
+                _ => continue,
+            };
+            let frequency = tuning.frequency(key)?;
+            let (start, length) = self.span(n);
+            let hit = length / step.ratchets as f64;
+            for j in 0..step.ratchets {
+                let onset = start + j as f64 * hit;
+                let duration = if step.slide && j + 1 == step.ratchets {
+                    start + length - onset + SLIDE_OVERLAP / self.steps_per_cycle as f64
+                } else {
+                    hit * step.gate
+                };
+                notes.push((key, Note::new(onset, duration, frequency, step.velocity, instrument)));
+            }
+        }
+        Ok(notes)
+    }
+
+    /// Timeline of the first `cycles` cycles at `config.cps`.
+    pub fn timeline(&self, config: &SynthConfig, tuning: &Tuning, cycles: usize, instrument: usize) -> Result<Timeline> {
+        Ok(TempoMap::from_config(config)?.timeline(&self.notes(tuning, cycles, instrument)?))
+    }
+
+    /// Note-on/note-off events of the first `cycles` cycles for a `VoiceAllocator`.
+    /// Slides overlap the next note, so a legato mono allocator ties them.
+    /// A slide into the same key is merged with the next note into one held note.
+    pub fn voice_events(&self, config: &SynthConfig, tuning: &Tuning, cycles: usize) -> Result<Vec<VoiceEvent>> {
+        let tempo = TempoMap::from_config(config)?;
+        let frame = |cycle: f64| tempo.sample_at(cycle, config.sample_rate).round() as usize;
+        let hits = self.hits(tuning, cycles, 0)?;
+        let mut events = Vec::new();
+        let mut tied = false;
+        let mut on = 0;
+        for (i, &(key, ref note)) in hits.iter().enumerate() {
+            if !tied {
+                on = frame(note.start);
+                let kind = EventKind::NoteOn { key, frequency: note.frequency, velocity: note.velocity };
+                events.push(VoiceEvent { frame: on, kind });
+            }
+            // At least one frame long, so the note-off cannot sort ahead of its own note-on.
+            let end = frame(note.start + note.duration).max(on + 1);
+            tied = hits.get(i + 1).is_some_and(|(next_key, next)| *next_key == key && frame(next.start) < end);
+            if !tied {
+                events.push(VoiceEvent { frame: end, kind: EventKind::NoteOff { key } });
+            }
+        }
+        // Note-offs go first so a key repeated on consecutive steps retriggers.
+        events.sort_by_key(|e| (e.frame, matches!(e.kind, EventKind::NoteOn { .. })));
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(notes: &[Note]) -> Vec<(f64, f64)> {
+        notes.iter().map(|n| (n.start, n.duration)).collect()
+    }
+
+    #[test]
+    fn test_straight_and_swung_steps() {
+        let steps = vec![Step::new(36), Step::rest(), Step::new(38).with_gate(1.0), Step::new(36).with_velocity(0.5)];
+        let mut sequencer = StepSequencer::new(steps, 4).unwrap();
+        let notes = sequencer.notes(&Tuning::default(), 2, 0).unwrap();
+        assert_eq!(spans(&notes[..3]), vec![(0.0, 0.125), (0.5, 0.25), (0.75, 0.125)]);
+        assert_eq!(notes.len(), 6);
+        assert_eq!(notes[3].start, 1.0);
+        assert_eq!(notes[2].velocity, 0.5);
+        assert!((notes[1].frequency - 73.416).abs() < 1e-3);
+
+        sequencer.swing = 0.75;
+        let notes = sequencer.notes(&Tuning::default(), 1, 0).unwrap();
+        // The off-beat step starts three quarters through its pair and gets a quarter of it.
+        assert_eq!(spans(&notes), vec![(0.0, 0.1875), (0.5, 0.375), (0.875, 0.0625)]);
+    }
+
+    #[test]
+    fn test_ratchets_and_slide() {
+        let steps = vec![Step::new(48).with_ratchets(4).with_gate(0.5), Step::new(50).with_slide(true), Step::new(52)];
+        let sequencer = StepSequencer::new(steps, 4).unwrap();
+        let notes = sequencer.notes(&Tuning::default(), 1, 0).unwrap();
+        let starts: Vec<f64> = notes.iter().map(|n| n.start).collect();
+        // Three steps loop over four per cycle, so the ratcheted step comes back at 0.75.
+        assert_eq!(starts, vec![0.0, 0.0625, 0.125, 0.1875, 0.25, 0.5, 0.75, 0.8125, 0.875, 0.9375]);
+        assert_eq!(notes[0].duration, 0.03125);
+        // The slide runs past the next onset at 0.5.
+        assert_eq!(notes[4].start + notes[4].duration, 0.5625);
+    }
+
+    #[test]
+    fn test_probability_is_seeded() {
+        let steps = vec![Step::new(60).with_probability(0.5); 16];
+        let mut sequencer = StepSequencer::new(steps, 16).unwrap();
+        sequencer.seed = 42;
+        let tuning = Tuning::default();
+        let first = sequencer.notes(&tuning, 8, 0).unwrap();
+        assert_eq!(first, sequencer.notes(&tuning, 8, 0).unwrap());
+        assert!(first.len() > 40 && first.len() < 88, "{}", first.len());
+        sequencer.seed = 43;
+        assert_ne!(first, sequencer.notes(&tuning, 8, 0).unwrap());
+
+        sequencer.steps = vec![Step::new(60).with_probability(0.0)];
+        assert!(sequencer.notes(&tuning, 4, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_steps() {
+        assert!(StepSequencer::new(vec![], 4).is_err());
+        assert!(StepSequencer::new(vec![Step::new(60)], 0).is_err());
+        assert!(StepSequencer::new(vec![Step::new(60).with_gate(0.0)], 4).is_err());
+        assert!(StepSequencer::new(vec![Step::new(60).with_ratchets(0)], 4).is_err());
+        assert!(StepSequencer::new(vec![Step::new(60).with_probability(1.5)], 4).is_err());
+        let mut sequencer = StepSequencer::new(vec![Step::new(60)], 4).unwrap();
+        sequencer.swing = 1.0;
+        assert!(sequencer.notes(&Tuning::default(), 1, 0).is_err());
+        sequencer.swing = 0.5;
+        sequencer.steps[0].gate = f64::NAN;
+        assert!(sequencer.notes(&Tuning::default(), 1, 0).is_err());
+    }
+
+    #[test]
+    fn test_swing_with_odd_steps() {
+        let mut sequencer = StepSequencer::new(vec![Step::new(60).with_gate(1.0)], 3).unwrap();
+        sequencer.swing = 0.75;
+        let notes = sequencer.notes(&Tuning::default(), 2, 0).unwrap();
+        let starts: Vec<f64> = notes.iter().map(|n| n.start).collect();
+        // Each cycle starts on the beat; the unpaired third step stays straight.
+        assert_eq!(starts, vec![0.0, 0.5, 2.0 / 3.0, 1.0, 1.5, 1.0 + 2.0 / 3.0]);
+        assert_eq!(notes[2].duration, 1.0 / 3.0);
+    }
+
+    #[test]
+    fn test_clocked_from_cps() {
+        let config = SynthConfig::new(1000, 20.0, 20000.0, 1.0, 0.0, 0.0, 2.0).unwrap();
+        let steps = vec![Step::new(45).with_slide(true), Step::new(57)];
+        let sequencer = StepSequencer::new(steps, 4).unwrap();
+        let timeline = sequencer.timeline(&config, &Tuning::default(), 1, 0).unwrap();
+        assert_eq!(timeline.notes[1].start, 0.125);
+        let events = sequencer.voice_events(&config, &Tuning::default(), 1).unwrap();
+        let frames: Vec<(usize, bool)> = events.iter().map(|e| (e.frame, matches!(e.kind, EventKind::NoteOn { .. }))).collect();
+        // The slide's note-off lands after the next note-on.
+        assert_eq!(frames[..3], [(0, true), (125, true), (156, false)]);
+    }
+
+    #[test]
+    fn test_sub_frame_ratchets_are_released() {
+        let config = SynthConfig::new(1000, 20.0, 20000.0, 1.0, 0.0, 0.0, 2.0).unwrap();
+        // 125 frames per step split into 200 ratchets.
+        let sequencer = StepSequencer::new(vec![Step::new(45).with_ratchets(200), Step::new(47).with_ratchets(300)], 4).unwrap();
+        let events = sequencer.voice_events(&config, &Tuning::default(), 1).unwrap();
+        let mut held = std::collections::HashSet::new();
+        for event in &events {
+            match event.kind {
+                EventKind::NoteOn { key, .. } => held.insert(key),
+                EventKind::NoteOff { key } => held.remove(&key),
+            };
+        }
+        assert!(held.is_empty(), "{:?}", held);
+    }
+
+    #[test]
+    fn test_slide_into_same_key_is_tied() {
+        let config = SynthConfig::new(1000, 20.0, 20000.0, 1.0, 0.0, 0.0, 2.0).unwrap();
+        let steps = vec![Step::new(45).with_slide(true), Step::new(45)];
+        let sequencer = StepSequencer::new(steps, 4).unwrap();
+        let events = sequencer.voice_events(&config, &Tuning::default(), 1).unwrap();
+        let frames: Vec<(usize, bool)> = events.iter().map(|e| (e.frame, matches!(e.kind, EventKind::NoteOn { .. }))).collect();
+        // Each slide and the repeated key that follows sound as one note.
+        assert_eq!(frames, vec![(0, true), (188, false), (250, true), (438, false)]);
+    }
+}
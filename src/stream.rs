@@ -0,0 +1,229 @@
+//! Block-based rendering straight to a writer.
+//! Sources are pulled a fixed number of frames at a time, so memory use does
+//! not grow with the length of the render.
+
+use std::io::{Seek, Write};
+use crate::buffer::AudioBuffer;
+use crate::error::{Error, Result};
+use crate::render::{RenderParams, Ugen};
+use crate::synth_config::SynthConfig;
+use crate::wav::{Quantizer, WavFormat, WavOptions};
+
+/// Anything that can produce interleaved audio a block at a time.
+pub trait BlockSource {
+    fn channels(&self) -> u16;
+    fn sample_rate(&self) -> u32;
+    /// Fills the front of `out` with whole interleaved frames and returns how many.
+    /// Returning 0 ends the stream.
+    fn fill(&mut self, out: &mut [f32]) -> usize;
+}
+
+/// Streams a single ugen note, equivalent to `render::render`.
+pub struct UgenSource<'a> {
+    config: &'a SynthConfig,
+    ugen: Ugen,
+    params: RenderParams,
+    t: u32,
+    end: u32,
+}
+
+impl<'a> UgenSource<'a> {
+    pub fn new(config: &'a SynthConfig, ugen: Ugen, params: RenderParams) -> Result<UgenSource<'a>> {
+        if params.duration < 0.0 {
+            return Err(Error::invalid("Duration must not be negative"));
+        }
+        config.check_frequency(params.freq)?;
+        let end = params.num_samples(config);
+        Ok(UgenSource { config, ugen, params, t: 0, end })
+    }
+}
+
+impl BlockSource for UgenSource<'_> {
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.config.sample_rate
+    }
+
+    fn fill(&mut self, out: &mut [f32]) -> usize {
+        let frames = (self.end - self.t).min(out.len() as u32) as usize;
+        for sample in out[..frames].iter_mut() {
+            *sample = self.params.amp * (self.ugen)(self.config, self.t, self.params.freq, self.params.bias);
+            self.t += 1;
+        }
+        frames
+    }
+}
+
+/// Streams an existing buffer.
+pub struct BufferSource<'a> {
+    buffer: &'a AudioBuffer,
+    frame: usize,
+}
+
+impl<'a> BufferSource<'a> {
+    pub fn new(buffer: &'a AudioBuffer) -> BufferSource<'a> {
+        BufferSource { buffer, frame: 0 }
+    }
+}
+
+impl BlockSource for BufferSource<'_> {
+    fn channels(&self) -> u16 {
+        self.buffer.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.buffer.sample_rate
+    }
+
+    fn fill(&mut self, out: &mut [f32]) -> usize {
+        let c = self.buffer.channels as usize;
+        let frames = (self.buffer.frames() - self.frame).min(out.len() / c);
+        let start = self.frame * c;
+        out[..frames * c].copy_from_slice(&self.buffer.samples[start..start + frames * c]);
+        self.frame += frames;
+        frames
+    }
+}
+
+fn block(source: &impl BlockSource, block_size: usize) -> Result<Vec<f32>> {
+    if block_size == 0 {
+        return Err(Error::invalid("Block size must be positive"));
+    }
+    Ok(vec![0.0; block_size * source.channels() as usize])
+}
+
+/// Renders `source` as a WAV file into any seekable writer, one block at a time.
+/// Returns the number of frames written.
+pub fn render_to_writer<W: Write + Seek>(source: &mut impl BlockSource, writer: W, options: &WavOptions, block_size: usize) -> Result<u64> {
+    let channels = source.channels();
+    let spec = options.format.spec(channels, source.sample_rate());
+    let mut wav = hound::WavWriter::new(writer, spec)?;
+    let mut quantizer = match options.format {
+        WavFormat::Float32 => None,
+        format => Some(Quantizer::new(format.bits_per_sample(), channels, options)?),
+    };
+    let mut buf = block(source, block_size)?;
+    let mut total = 0;
+    loop {
+        let frames = source.fill(&mut buf);
+        if frames == 0 {
+            break;
+        }
+        for (i, &sample) in buf[..frames * channels as usize].iter().enumerate() {
+            match quantizer.as_mut() {
+                None => wav.write_sample(sample)?,
+                Some(q) => wav.write_sample(q.quantize(sample, i % channels as usize))?,
+            }
+        }
+        total += frames as u64;
+    }
+    wav.finalize()?;
+    Ok(total)
+}
+
+/// Writes a canonical 44 byte WAV header whose sizes are left at their maximum,
+/// the usual convention for streams whose length is unknown.
+fn write_streaming_header<W: Write>(writer: &mut W, channels: u16, sample_rate: u32, format: WavFormat) -> Result<()> {
+    let bits = format.bits_per_sample();
+    let block_align = channels * bits / 8;
+    let format_tag: u16 = if format == WavFormat::Float32 { 3 } else { 1 };
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&u32::MAX.to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&format_tag.to_le_bytes())?;
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&bits.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&u32::MAX.to_le_bytes())?;
+    Ok(())
+}
+
+/// Renders `source` as a WAV stream into a writer that cannot seek, such as stdout.
+/// Returns the number of frames written.
+pub fn render_to_stream<W: Write>(source: &mut impl BlockSource, mut writer: W, options: &WavOptions, block_size: usize) -> Result<u64> {
+    let channels = source.channels();
+    write_streaming_header(&mut writer, channels, source.sample_rate(), options.format)?;
+    let mut quantizer = match options.format {
+        WavFormat::Float32 => None,
+        format => Some(Quantizer::new(format.bits_per_sample(), channels, options)?),
+    };
+    let width = options.format.bits_per_sample() as usize / 8;
+    let mut buf = block(source, block_size)?;
+    let mut bytes = Vec::with_capacity(buf.len() * 4);
+    let mut total = 0;
+    loop {
+        let frames = source.fill(&mut buf);
+        if frames == 0 {
+            break;
+        }
+        bytes.clear();
+        for (i, &sample) in buf[..frames * channels as usize].iter().enumerate() {
+            match quantizer.as_mut() {
+                None => bytes.extend_from_slice(&sample.to_le_bytes()),
+                Some(q) => bytes.extend_from_slice(&q.quantize(sample, i % channels as usize).to_le_bytes()[..width]),
+            }
+        }
+        writer.write_all(&bytes)?;
+        total += frames as u64;
+    }
+    writer.flush()?;
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::render::render;
+    use crate::time_forms;
+
+    fn test_config() -> SynthConfig {
+        SynthConfig::new(8000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0).unwrap()
+    }
+
+    #[test]
+    fn test_streamed_matches_in_memory() {
+        let config = test_config();
+        let params = RenderParams::new(0.3, 220.0, 0.5);
+        let expected = render(&config, &(time_forms::sine as Ugen), &params).unwrap();
+
+        let mut source = UgenSource::new(&config, time_forms::sine, params).unwrap();
+        let mut cursor = Cursor::new(Vec::new());
+        let frames = render_to_writer(&mut source, &mut cursor, &WavOptions::default(), 256).unwrap();
+        assert_eq!(frames, 2400);
+
+        cursor.set_position(0);
+        let mut reader = hound::WavReader::new(cursor).unwrap();
+        let samples: Vec<f32> = reader.samples::<f32>().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, expected.samples);
+    }
+
+    #[test]
+    fn test_seekless_stream() {
+        let buffer = AudioBuffer::from_channels(&[vec![0.5; 100], vec![-0.5; 100]], 8000).unwrap();
+        let mut out = Vec::new();
+        let options = WavOptions { dither: crate::wav::Dither::None, ..WavOptions::new(WavFormat::Int16) };
+        let frames = render_to_stream(&mut BufferSource::new(&buffer), &mut out, &options, 33).unwrap();
+        assert_eq!(frames, 100);
+        assert_eq!(out.len(), 44 + 100 * 2 * 2);
+        assert_eq!(&out[0..4], b"RIFF");
+        assert_eq!(&out[36..40], b"data");
+        assert_eq!(i16::from_le_bytes([out[44], out[45]]), 16384);
+        assert_eq!(i16::from_le_bytes([out[46], out[47]]), -16384);
+    }
+
+    #[test]
+    fn test_zero_block_size() {
+        let config = test_config();
+        let mut source = UgenSource::new(&config, time_forms::sine, RenderParams::new(0.1, 220.0, 0.5)).unwrap();
+        let result = render_to_writer(&mut source, Cursor::new(Vec::new()), &WavOptions::default(), 0);
+        assert!(result.is_err());
+    }
+}
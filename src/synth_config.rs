@@ -1,3 +1,5 @@
+use crate::error::{Error, Result};
+
 pub struct SynthConfig {
     pub sample_rate: u32,
     pub min_frequency: f32,
@@ -9,8 +11,17 @@ pub struct SynthConfig {
 }
 
 impl SynthConfig {
-    pub fn new(sample_rate: u32, min_frequency: f32, max_frequency: f32, amplitude_scaling: f32, phase_offset: f32, tuning_offset_hz: f32, cps: f32) -> SynthConfig {
-        SynthConfig { 
+    pub fn new(sample_rate: u32, min_frequency: f32, max_frequency: f32, amplitude_scaling: f32, phase_offset: f32, tuning_offset_hz: f32, cps: f32) -> Result<SynthConfig> {
+        if sample_rate == 0 {
+            return Err(Error::invalid("Sample rate must be positive"));
+        }
+        if min_frequency < 0.0 || min_frequency > max_frequency {
+            return Err(Error::invalid("Frequency range must satisfy 0 <= min <= max"));
+        }
+        if cps <= 0.0 {
+            return Err(Error::invalid("CPS must be a tempo"));
+        }
+        Ok(SynthConfig { 
             sample_rate,
             min_frequency,
             max_frequency,
@@ -18,6 +29,14 @@ impl SynthConfig {
             phase_offset,
             tuning_offset_hz,
             cps
+        })
+    }
+
+    /// Checks that `freq` lies within the configured frequency range.
+    pub fn check_frequency(&self, freq: f32) -> Result<()> {
+        if freq < self.min_frequency || freq > self.max_frequency {
+            return Err(Error::FrequencyOutOfRange(freq));
         }
+        Ok(())
     }
 }
@@ -0,0 +1,323 @@
+//! Tempo map: tempo changes and ramps measured in cycles, time signatures,
+//! and conversions between samples, seconds, cycles and bars:beats:ticks.
+//!
+//! Ramps are defined over musical position, so the time spent in a ramp has a
+//! closed form and every conversion is exact in both directions.
+
+use crate::error::{Error, Result};
+use crate::sequence::{Note, Timeline};
+use crate::synth_config::SynthConfig;
+
+/// Resolution of `BarBeatTick::tick`.
+pub const TICKS_PER_QUARTER: u32 = 960;
+
+/// How the tempo reaches a change from the previous one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ramp {
+    /// Holds the previous tempo and jumps at the change.
+    Step,
+    /// Tempo moves linearly with position.
+    Linear,
+    /// Tempo moves by a constant ratio per cycle.
+    Exponential,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TempoChange {
+    pub cycle: f64,
+    /// Cycles per second reached at `cycle`.
+    pub cps: f64,
+    pub ramp: Ramp,
+}
+
+/// A meter starting at `bar` (1-based) and holding until the next one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub bar: u32,
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+/// Musical position with 1-based bar and beat, as shown by sequencers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BarBeatTick {
+    pub bar: u32,
+    pub beat: u32,
+    pub tick: u32,
+}
+
+pub struct TempoMap {
+    quarters_per_cycle: f64,
+    changes: Vec<TempoChange>,
+    /// Seconds at which each change starts.
+    seconds: Vec<f64>,
+    signatures: Vec<TimeSignature>,
+}
+
+impl TempoMap {
+    /// Constant tempo of `cps` in 4/4, with `quarters_per_cycle` quarter notes per cycle.
+    pub fn new(cps: f64, quarters_per_cycle: f64) -> Result<TempoMap> {
+        if !cps.is_finite() || cps <= 0.0 || !quarters_per_cycle.is_finite() || quarters_per_cycle <= 0.0 {
+            return Err(Error::invalid("Tempo and quarters per cycle must be positive"));
+        }
+        Ok(TempoMap {
+            quarters_per_cycle,
+            changes: vec![TempoChange { cycle: 0.0, cps, ramp: Ramp::Step }],
+            seconds: vec![0.0],
+            signatures: vec![TimeSignature { bar: 1, numerator: 4, denominator: 4 }],
+        })
+    }
+
+    /// Constant tempo of `config.cps` with one 4/4 bar per cycle.
+    pub fn from_config(config: &SynthConfig) -> Result<TempoMap> {
+        TempoMap::new(config.cps as f64, 4.0)
+    }
+
+    pub fn changes(&self) -> &[TempoChange] {
+        &self.changes
+    }
+
+    pub fn time_signatures(&self) -> &[TimeSignature] {
+        &self.signatures
+    }
+
+    /// Reaches `cps` at `cycle` via `ramp`, replacing any change already there.
+    /// The first change is always at cycle 0; its ramp is ignored.
+    pub fn set_tempo(&mut self, cycle: f64, cps: f64, ramp: Ramp) -> Result<()> {
+        if !cps.is_finite() || cps <= 0.0 || !cycle.is_finite() || cycle < 0.0 {
+            return Err(Error::invalid("Tempo changes need a positive tempo at a non-negative cycle"));
+        }
+        let change = TempoChange { cycle, cps, ramp };
+        match self.changes.binary_search_by(|c| c.cycle.total_cmp(&cycle)) {
+            Ok(i) => self.changes[i] = change,
+            Err(i) => self.changes.insert(i, change),
+        }
+        self.seconds = vec![0.0; self.changes.len()];
+        for i in 1..self.changes.len() {
+            let (a, b) = (&self.changes[i - 1], &self.changes[i]);
+            self.seconds[i] = self.seconds[i - 1] + segment_seconds(a, b, b.cycle - a.cycle);
+        }
+        Ok(())
+    }
+
+    /// Switches meter at the start of `bar`, replacing any signature already there.
+    pub fn set_time_signature(&mut self, bar: u32, numerator: u32, denominator: u32) -> Result<()> {
+        if bar == 0 || numerator == 0 || !denominator.is_power_of_two() || !(4 * TICKS_PER_QUARTER).is_multiple_of(denominator) {
+            return Err(Error::invalid(format!("Invalid time signature {}/{} at bar {}", numerator, denominator, bar)));
+        }
+        let signature = TimeSignature { bar, numerator, denominator };
+        match self.signatures.binary_search_by_key(&bar, |s| s.bar) {
+            Ok(i) => self.signatures[i] = signature,
+            Err(i) => self.signatures.insert(i, signature),
+        }
+        Ok(())
+    }
+
+    /// Segment containing `cycle` and the change that ends it, if any.
+    fn segment(&self, cycle: f64) -> (usize, Option<&TempoChange>) {
+        let i = self.changes.partition_point(|c| c.cycle <= cycle).max(1) - 1;
+        (i, self.changes.get(i + 1))
+    }
+
+    /// Tempo in cycles per second at `cycle`.
+    pub fn cps_at(&self, cycle: f64) -> f64 {
+        let (i, next) = self.segment(cycle);
+        let a = &self.changes[i];
+        match next {
+            Some(b) if b.ramp != Ramp::Step => {
+                let x = (cycle - a.cycle) / (b.cycle - a.cycle);
+                match b.ramp {
+                    Ramp::Linear => a.cps + (b.cps - a.cps) * x,
+                    _ => a.cps * (b.cps / a.cps).powf(x),
+                }
+            }
+            _ => a.cps,
+        }
+    }
+
+    pub fn seconds_at(&self, cycle: f64) -> f64 {
+        let (i, next) = self.segment(cycle);
+        let a = &self.changes[i];
+        let b = next.copied().unwrap_or(TempoChange { cycle: f64::INFINITY, cps: a.cps, ramp: Ramp::Step });
+        self.seconds[i] + segment_seconds(a, &b, cycle - a.cycle)
+    }
+
+    pub fn cycle_at(&self, seconds: f64) -> f64 {
+        let i = self.seconds.partition_point(|&s| s <= seconds).max(1) - 1;
+        let a = &self.changes[i];
+        let t = seconds - self.seconds[i];
+        let b = match self.changes.get(i + 1) {
+            Some(b) if b.ramp != Ramp::Step => b,
+            _ => return a.cycle + t * a.cps,
+        };
+        let length = b.cycle - a.cycle;
+        let x = match b.ramp {
+            Ramp::Linear if b.cps != a.cps => (t * (b.cps - a.cps) / length).exp_m1() * a.cps * length / (b.cps - a.cps),
+            Ramp::Exponential if b.cps != a.cps => {
+                let log_ratio = (b.cps / a.cps).ln();
+                -length * (-t * a.cps * log_ratio / length).ln_1p() / log_ratio
+            }
+            _ => t * a.cps,
+        };
+        a.cycle + x
+    }
+
+    /// Sample position of `cycle`, unrounded.
+    pub fn sample_at(&self, cycle: f64, sample_rate: u32) -> f64 {
+        self.seconds_at(cycle) * sample_rate as f64
+    }
+
+    pub fn cycle_at_sample(&self, sample: f64, sample_rate: u32) -> f64 {
+        self.cycle_at(sample / sample_rate as f64)
+    }
+
+    /// First tick of each signature's opening bar.
+    fn signature_starts(&self) -> Vec<u64> {
+        let mut starts = vec![0];
+        for pair in self.signatures.windows(2) {
+            let bars = (pair[1].bar - pair[0].bar) as u64;
+            starts.push(starts[starts.len() - 1] + bars * bar_ticks(&pair[0]));
+        }
+        starts
+    }
+
+    /// Position of `cycle`, rounded to the nearest tick.
+    pub fn bar_beat_tick(&self, cycle: f64) -> Result<BarBeatTick> {
+        let ticks = (cycle * self.quarters_per_cycle * TICKS_PER_QUARTER as f64).round();
+        if !ticks.is_finite() || ticks < 0.0 {
+            return Err(Error::invalid("Bar positions start at cycle 0"));
+        }
+        let ticks = ticks as u64;
+        let starts = self.signature_starts();
+        let i = starts.partition_point(|&s| s <= ticks) - 1;
+        let signature = &self.signatures[i];
+        let (bar_len, beat_len) = (bar_ticks(signature), beat_ticks(signature));
+        let within = ticks - starts[i];
+        let bar = signature.bar as u64 + within / bar_len;
+        let within = within % bar_len;
+        Ok(BarBeatTick { bar: bar as u32, beat: (within / beat_len) as u32 + 1, tick: (within % beat_len) as u32 })
+    }
+
+    pub fn cycle_at_bar_beat_tick(&self, position: BarBeatTick) -> Result<f64> {
+        if position.bar == 0 || position.beat == 0 {
+            return Err(Error::invalid("Bars and beats are numbered from 1"));
+        }
+        let i = self.signatures.partition_point(|s| s.bar <= position.bar) - 1;
+        let signature = &self.signatures[i];
+        let beat_len = beat_ticks(signature);
+        if position.beat > signature.numerator || position.tick as u64 >= beat_len {
+            return Err(Error::invalid(format!("{:?} does not fit a {}/{} bar", position, signature.numerator, signature.denominator)));
+        }
+        let ticks = self.signature_starts()[i]
+            + (position.bar - signature.bar) as u64 * bar_ticks(signature)
+            + (position.beat - 1) as u64 * beat_len
+            + position.tick as u64;
+        Ok(ticks as f64 / TICKS_PER_QUARTER as f64 / self.quarters_per_cycle)
+    }
+
+    /// Converts notes whose `start` and `duration` are in cycles into a timeline in seconds.
+    pub fn timeline(&self, notes: &[Note]) -> Timeline {
+        Timeline::new(notes.iter().map(|note| {
+            let start = self.seconds_at(note.start);
+            let end = self.seconds_at(note.start + note.duration);
+            Note { start, duration: end - start, ..*note }
+        }).collect())
+    }
+}
+
+fn beat_ticks(signature: &TimeSignature) -> u64 {
+    (4 * TICKS_PER_QUARTER / signature.denominator) as u64
+}
+
+fn bar_ticks(signature: &TimeSignature) -> u64 {
+    signature.numerator as u64 * beat_ticks(signature)
+}
+
+/// Seconds to cover `x` cycles from `a` on the way to `b`.
+fn segment_seconds(a: &TempoChange, b: &TempoChange, x: f64) -> f64 {
+    let length = b.cycle - a.cycle;
+    match b.ramp {
+        Ramp::Linear if b.cps != a.cps => {
+            let slope = (b.cps - a.cps) / length;
+            ((a.cps + slope * x) / a.cps).ln() / slope
+        }
+        Ramp::Exponential if b.cps != a.cps => {
+            let log_ratio = (b.cps / a.cps).ln();
+            -length * (-x * log_ratio / length).exp_m1() / (a.cps * log_ratio)
+        }
+        _ => x / a.cps,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_constant_and_steps() {
+        let mut map = TempoMap::new(0.5, 4.0).unwrap();
+        assert!(close(map.seconds_at(3.0), 6.0));
+        assert!(close(map.sample_at(1.0, 48000), 96000.0));
+        map.set_tempo(2.0, 1.0, Ramp::Step).unwrap();
+        assert!(close(map.seconds_at(2.0), 4.0));
+        assert!(close(map.seconds_at(5.0), 7.0));
+        assert!(close(map.cycle_at(7.0), 5.0));
+        assert!(close(map.cycle_at(1.0), 0.5));
+        assert!(close(map.cps_at(1.9), 0.5));
+        assert!(map.set_tempo(1.0, 0.0, Ramp::Step).is_err());
+    }
+
+    #[test]
+    fn test_ramps() {
+        for ramp in [Ramp::Linear, Ramp::Exponential] {
+            let mut map = TempoMap::new(1.0, 4.0).unwrap();
+            map.set_tempo(4.0, 2.0, ramp).unwrap();
+            assert!(close(map.cps_at(4.0), 2.0));
+            // Midpoints: arithmetic mean for linear, geometric for exponential.
+            let mid = if ramp == Ramp::Linear { 1.5 } else { 2f64.sqrt() };
+            assert!(close(map.cps_at(2.0), mid));
+
+            // Numerically integrate dt = dx / cps.
+            let steps = 100_000;
+            let numeric: f64 = (0..steps).map(|i| 4.0 / steps as f64 / map.cps_at((i as f64 + 0.5) * 4.0 / steps as f64)).sum();
+            assert!((map.seconds_at(4.0) - numeric).abs() < 1e-6);
+
+            for cycle in [0.0, 0.3, 1.7, 3.99, 4.0, 6.5] {
+                assert!(close(map.cycle_at(map.seconds_at(cycle)), cycle), "{:?} at {}", ramp, cycle);
+            }
+            assert!(close(map.seconds_at(6.0) - map.seconds_at(4.0), 1.0));
+        }
+    }
+
+    #[test]
+    fn test_bar_beat_tick() {
+        let mut map = TempoMap::new(1.0, 4.0).unwrap();
+        assert_eq!(map.bar_beat_tick(0.0).unwrap(), BarBeatTick { bar: 1, beat: 1, tick: 0 });
+        assert_eq!(map.bar_beat_tick(1.375).unwrap(), BarBeatTick { bar: 2, beat: 2, tick: 480 });
+
+        map.set_time_signature(3, 6, 8).unwrap();
+        map.set_time_signature(5, 3, 4).unwrap();
+        // Bars 3 and 4 are 3 quarters long each, so bar 5 starts at 14 quarters.
+        assert_eq!(map.bar_beat_tick(3.5).unwrap(), BarBeatTick { bar: 5, beat: 1, tick: 0 });
+        assert_eq!(map.bar_beat_tick(2.25).unwrap(), BarBeatTick { bar: 3, beat: 3, tick: 0 });
+        let position = BarBeatTick { bar: 6, beat: 2, tick: 240 };
+        let cycle = map.cycle_at_bar_beat_tick(position).unwrap();
+        assert!(close(cycle, (17.0 + 1.25) / 4.0));
+        assert_eq!(map.bar_beat_tick(cycle).unwrap(), position);
+        assert!(map.cycle_at_bar_beat_tick(BarBeatTick { bar: 3, beat: 7, tick: 0 }).is_err());
+        assert!(map.set_time_signature(2, 3, 6).is_err());
+    }
+
+    #[test]
+    fn test_timeline() {
+        let mut map = TempoMap::new(1.0, 4.0).unwrap();
+        map.set_tempo(1.0, 2.0, Ramp::Step).unwrap();
+        let timeline = map.timeline(&[Note::new(0.5, 1.0, 440.0, 1.0, 0)]);
+        assert!(close(timeline.notes[0].start, 0.5));
+        assert!(close(timeline.notes[0].duration, 0.75));
+    }
+}
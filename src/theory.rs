@@ -0,0 +1,476 @@
+//! Music theory helpers: scales and modes, chords with inversions and
+//! voicings, Roman-numeral progressions, and an arpeggiator.
+//!
+//! Everything works on MIDI keys; `pattern::to_timeline` turns the resulting
+//! events into a timeline under any tuning and tempo map.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::error::{Error, Result};
+use crate::pattern::Event;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScaleKind {
+    Major,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Minor,
+    Locrian,
+    HarmonicMinor,
+    MelodicMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+    WholeTone,
+    Chromatic,
+}
+
+impl ScaleKind {
+    /// Semitones above the tonic for each degree.
+    pub fn intervals(&self) -> &'static [i32] {
+        match self {
+            ScaleKind::Major => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleKind::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            ScaleKind::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            ScaleKind::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            ScaleKind::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            ScaleKind::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            ScaleKind::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            ScaleKind::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            ScaleKind::MelodicMinor => &[0, 2, 3, 5, 7, 9, 11],
+            ScaleKind::MajorPentatonic => &[0, 2, 4, 7, 9],
+            ScaleKind::MinorPentatonic => &[0, 3, 5, 7, 10],
+            ScaleKind::Blues => &[0, 3, 5, 6, 7, 10],
+            ScaleKind::WholeTone => &[0, 2, 4, 6, 8, 10],
+            ScaleKind::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        }
+    }
+}
+
+/// A scale rooted on a tonic key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Key {
+    pub tonic: u8,
+    pub scale: ScaleKind,
+}
+
+impl Key {
+    pub fn new(tonic: u8, scale: ScaleKind) -> Key {
+        Key { tonic, scale }
+    }
+
+    /// Key of the zero-based `degree`, continuing into neighbouring octaves.
+    pub fn degree(&self, degree: i32) -> Result<u8> {
+        let intervals = self.scale.intervals();
+        let n = intervals.len() as i32;
+        let key = self.tonic as i32 + 12 * degree.div_euclid(n) + intervals[degree.rem_euclid(n) as usize];
+        to_key(key)
+    }
+
+    /// Keys of the scale from `low` to `high` inclusive.
+    pub fn keys(&self, low: u8, high: u8) -> Vec<u8> {
+        let intervals = self.scale.intervals();
+        (low..=high).filter(|&k| intervals.contains(&((k as i32 - self.tonic as i32).rem_euclid(12)))).collect()
+    }
+
+    /// Chord of `size` notes stacked in scale thirds on the zero-based `degree`.
+    pub fn diatonic_chord(&self, degree: i32, size: usize) -> Result<Vec<u8>> {
+        (0..size as i32).map(|i| self.degree(degree + 2 * i)).collect()
+    }
+
+    /// Parses space-separated Roman numerals such as `"I vi ii7 V7"` or
+    /// `"i bVI bIII bVII"`. Case picks major or minor; suffixes add `°`/`o`
+    /// (diminished), `+`, `ø7`, `7`, `maj7`, `9`, `11`, `13`, `sus2` and `sus4`.
+    /// Plain numerals follow the key's scale, which must have seven degrees.
+    /// A `b` or `#` alters the major-scale degree instead, so `bVI` is a minor
+    /// sixth above the tonic in any key.
+    pub fn progression(&self, numerals: &str) -> Result<Vec<Chord>> {
+        let intervals = self.scale.intervals();
+        if intervals.len() != 7 {
+            return Err(Error::invalid("Roman numerals need a seven-note scale"));
+        }
+        numerals.split_whitespace().map(|numeral| {
+            let invalid = || Error::invalid(format!("Invalid Roman numeral {:?}", numeral));
+            let (shift, rest) = match numeral.chars().next() {
+                Some('b') => (-1, &numeral[1..]),
+                Some('#') => (1, &numeral[1..]),
+                _ => (0, numeral),
+            };
+            let end = rest.find(|c: char| !"IViv".contains(c)).unwrap_or(rest.len());
+            let (roman, suffix) = rest.split_at(end);
+            let degree = ["I", "II", "III", "IV", "V", "VI", "VII"].iter()
+                .position(|r| r.eq_ignore_ascii_case(roman))
+                .ok_or_else(invalid)?;
+            let minor = roman.chars().all(|c| c.is_ascii_lowercase());
+            if !minor && roman.chars().any(|c| c.is_ascii_lowercase()) {
+                return Err(invalid());
+            }
+            let quality = match (suffix, minor) {
+                ("", false) => ChordQuality::Major,
+                ("", true) => ChordQuality::Minor,
+                ("°" | "o", _) => ChordQuality::Diminished,
+                ("°7" | "o7", _) => ChordQuality::Diminished7,
+                ("ø" | "ø7", _) => ChordQuality::HalfDiminished7,
+                ("+", _) => ChordQuality::Augmented,
+                ("sus2", _) => ChordQuality::Sus2,
+                ("sus4", _) => ChordQuality::Sus4,
+                ("7", false) => ChordQuality::Dominant7,
+                ("7", true) => ChordQuality::Minor7,
+                ("maj7", false) => ChordQuality::Major7,
+                ("maj7", true) => ChordQuality::MinorMajor7,
+                ("9", false) => ChordQuality::Dominant9,
+                ("9", true) => ChordQuality::Minor9,
+                ("maj9", false) => ChordQuality::Major9,
+                ("11", false) => ChordQuality::Dominant11,
+                ("11", true) => ChordQuality::Minor11,
+                ("13", false) => ChordQuality::Dominant13,
+                ("13", true) => ChordQuality::Minor13,
+                ("maj13", false) => ChordQuality::Major13,
+                _ => return Err(invalid()),
+            };
+            let base = if shift == 0 { intervals[degree] } else { ScaleKind::Major.intervals()[degree] };
+            let root = to_key(self.tonic as i32 + base + shift)?;
+            Ok(Chord::new(root, quality))
+        }).collect()
+    }
+}
+
+fn to_key(key: i32) -> Result<u8> {
+    u8::try_from(key).ok().filter(|&k| k < 128).ok_or_else(|| Error::invalid(format!("Key {} is outside the MIDI range", key)))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChordQuality {
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+    Sus2,
+    Sus4,
+    Dominant7,
+    Major7,
+    Minor7,
+    MinorMajor7,
+    HalfDiminished7,
+    Diminished7,
+    Dominant9,
+    Major9,
+    Minor9,
+    Dominant11,
+    Minor11,
+    Dominant13,
+    Major13,
+    Minor13,
+}
+
+impl ChordQuality {
+    /// Semitones above the root, lowest first.
+    pub fn intervals(&self) -> &'static [i32] {
+        match self {
+            ChordQuality::Major => &[0, 4, 7],
+            ChordQuality::Minor => &[0, 3, 7],
+            ChordQuality::Diminished => &[0, 3, 6],
+            ChordQuality::Augmented => &[0, 4, 8],
+            ChordQuality::Sus2 => &[0, 2, 7],
+            ChordQuality::Sus4 => &[0, 5, 7],
+            ChordQuality::Dominant7 => &[0, 4, 7, 10],
+            ChordQuality::Major7 => &[0, 4, 7, 11],
+            ChordQuality::Minor7 => &[0, 3, 7, 10],
+            ChordQuality::MinorMajor7 => &[0, 3, 7, 11],
+            ChordQuality::HalfDiminished7 => &[0, 3, 6, 10],
+            ChordQuality::Diminished7 => &[0, 3, 6, 9],
+            ChordQuality::Dominant9 => &[0, 4, 7, 10, 14],
+            ChordQuality::Major9 => &[0, 4, 7, 11, 14],
+            ChordQuality::Minor9 => &[0, 3, 7, 10, 14],
+            ChordQuality::Dominant11 => &[0, 4, 7, 10, 14, 17],
+            ChordQuality::Minor11 => &[0, 3, 7, 10, 14, 17],
+            ChordQuality::Dominant13 => &[0, 4, 7, 10, 14, 17, 21],
+            ChordQuality::Major13 => &[0, 4, 7, 11, 14, 17, 21],
+            ChordQuality::Minor13 => &[0, 3, 7, 10, 14, 17, 21],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Voicing {
+    /// Notes packed within the smallest span.
+    Close,
+    /// Second-highest note dropped an octave.
+    Drop2,
+    /// Third-highest note dropped an octave.
+    Drop3,
+    /// Every other note raised an octave.
+    Open,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chord {
+    pub root: u8,
+    pub quality: ChordQuality,
+    /// Number of lowest notes moved up an octave.
+    pub inversion: usize,
+    pub voicing: Voicing,
+}
+
+impl Chord {
+    pub fn new(root: u8, quality: ChordQuality) -> Chord {
+        Chord { root, quality, inversion: 0, voicing: Voicing::Close }
+    }
+
+    pub fn with_inversion(self, inversion: usize) -> Chord {
+        Chord { inversion, ..self }
+    }
+
+    pub fn with_voicing(self, voicing: Voicing) -> Chord {
+        Chord { voicing, ..self }
+    }
+
+    /// Keys of the chord in ascending order.
+    pub fn keys(&self) -> Result<Vec<u8>> {
+        let mut keys: Vec<i32> = self.quality.intervals().iter().map(|i| self.root as i32 + i).collect();
+        for _ in 0..self.inversion {
+            let lowest = keys.remove(0);
+            keys.push(lowest + 12);
+        }
+        let n = keys.len();
+        match self.voicing {
+            Voicing::Close => {}
+            Voicing::Drop2 if n >= 2 => keys[n - 2] -= 12,
+            Voicing::Drop3 if n >= 3 => keys[n - 3] -= 12,
+            Voicing::Open => keys.iter_mut().skip(1).step_by(2).for_each(|k| *k += 12),
+            _ => return Err(Error::invalid(format!("{:?} needs more notes than {:?} has", self.voicing, self.quality))),
+        }
+        keys.sort();
+        keys.into_iter().map(to_key).collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArpMode {
+    Up,
+    Down,
+    /// Up then down without repeating the top and bottom notes.
+    UpDown,
+    /// Seeded random choice on every step.
+    Random,
+    /// Keys in the order given.
+    AsPlayed,
+}
+
+pub struct Arpeggiator {
+    pub mode: ArpMode,
+    /// Octaves the pattern spans, at least 1.
+    pub octaves: u32,
+    /// Steps per cycle, so the rate follows the tempo.
+    pub rate: f64,
+    /// Fraction of a step each note sounds, in `(0, 1]`.
+    pub gate: f64,
+    pub seed: u64,
+}
+
+impl Arpeggiator {
+    pub fn new(mode: ArpMode, octaves: u32, rate: f64) -> Result<Arpeggiator> {
+        let arpeggiator = Arpeggiator { mode, octaves, rate, gate: 0.5, seed: 0 };
+        arpeggiator.check()?;
+        Ok(arpeggiator)
+    }
+
+    /// The fields are public, so they are checked again before every use.
+    fn check(&self) -> Result<()> {
+        if self.octaves == 0 || !self.rate.is_finite() || self.rate <= 0.0 {
+            return Err(Error::invalid("Arpeggiators need at least one octave and a positive rate"));
+        }
+        if !(self.gate > 0.0 && self.gate <= 1.0) {
+            return Err(Error::invalid("Arpeggiator gate must lie in (0, 1]"));
+        }
+        Ok(())
+    }
+
+    fn check_span(start: f64, length: f64) -> Result<()> {
+        if !start.is_finite() || !length.is_finite() || length < 0.0 {
+            return Err(Error::invalid("Arpeggio start and length must be finite, with a non-negative length"));
+        }
+        Ok(())
+    }
+
+    /// Keys cycled through for a held chord.
+    fn order(&self, keys: &[u8]) -> Vec<u8> {
+        let mut base = keys.to_vec();
+        if self.mode != ArpMode::AsPlayed {
+            base.sort();
+            base.dedup();
+        }
+        let mut pool: Vec<u8> = (0..self.octaves)
+            .flat_map(|octave| base.iter().map(move |&k| k as u32 + 12 * octave))
+            .filter(|&k| k < 128)
+            .map(|k| k as u8)
+            .collect();
+        match self.mode {
+            ArpMode::Down => pool.reverse(),
+            ArpMode::UpDown if pool.len() > 2 => {
+                let down: Vec<u8> = pool[1..pool.len() - 1].iter().rev().copied().collect();
+                pool.extend(down);
+            }
+            _ => {}
+        }
+        pool
+    }
+
+    fn arpeggiate(&self, keys: &[u8], start: f64, length: f64, rng: &mut StdRng, out: &mut Vec<Event>) {
+        let order = self.order(keys);
+        if order.is_empty() {
+            return;
+        }
+        let step = 1.0 / self.rate;
+        let end = start + length;
+        let mut i = 0;
+        loop {
+            let onset = start + i as f64 * step;
+            if onset >= end - 1e-9 {
+                break;
+            }
+            let key = match self.mode {
+                ArpMode::Random => order[rng.gen_range(0..order.len())],
+                _ => order[i % order.len()],
+            };
+            out.push(Event { start: onset, duration: (step * self.gate).min(end - onset), key });
+            i += 1;
+        }
+    }
+
+    /// Events for `keys` held from `start` for `length` cycles.
+    pub fn events(&self, keys: &[u8], start: f64, length: f64) -> Result<Vec<Event>> {
+        self.check()?;
+        Arpeggiator::check_span(start, length)?;
+        let mut out = Vec::new();
+        self.arpeggiate(keys, start, length, &mut StdRng::seed_from_u64(self.seed), &mut out);
+        Ok(out)
+    }
+
+    /// Events for a progression, each chord held for `cycles_per_chord` cycles from cycle 0.
+    pub fn progression(&self, chords: &[Chord], cycles_per_chord: f64) -> Result<Vec<Event>> {
+        self.check()?;
+        Arpeggiator::check_span(0.0, cycles_per_chord * chords.len() as f64)?;
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut out = Vec::new();
+        for (i, chord) in chords.iter().enumerate() {
+            self.arpeggiate(&chord.keys()?, i as f64 * cycles_per_chord, cycles_per_chord, &mut rng, &mut out);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(events: &[Event]) -> Vec<u8> {
+        events.iter().map(|e| e.key).collect()
+    }
+
+    #[test]
+    fn test_scales() {
+        let d_dorian = Key::new(62, ScaleKind::Dorian);
+        assert_eq!(d_dorian.keys(60, 72), vec![60, 62, 64, 65, 67, 69, 71, 72]);
+        assert_eq!(d_dorian.degree(7).unwrap(), 74);
+        assert_eq!(d_dorian.degree(-1).unwrap(), 60);
+        let c_major = Key::new(60, ScaleKind::Major);
+        assert_eq!(c_major.diatonic_chord(1, 4).unwrap(), vec![62, 65, 69, 72]);
+        assert_eq!(c_major.diatonic_chord(6, 3).unwrap(), vec![71, 74, 77]);
+        assert!(Key::new(120, ScaleKind::Major).degree(7).is_err());
+    }
+
+    #[test]
+    fn test_chords() {
+        let c = Chord::new(60, ChordQuality::Major);
+        assert_eq!(c.keys().unwrap(), vec![60, 64, 67]);
+        assert_eq!(c.with_inversion(1).keys().unwrap(), vec![64, 67, 72]);
+        assert_eq!(c.with_inversion(2).keys().unwrap(), vec![67, 72, 76]);
+        let c7 = Chord::new(60, ChordQuality::Major7);
+        assert_eq!(c7.with_voicing(Voicing::Drop2).keys().unwrap(), vec![55, 60, 64, 71]);
+        assert_eq!(c7.with_voicing(Voicing::Drop3).keys().unwrap(), vec![52, 60, 67, 71]);
+        assert_eq!(c7.with_voicing(Voicing::Open).keys().unwrap(), vec![60, 67, 76, 83]);
+        assert_eq!(Chord::new(48, ChordQuality::Dominant13).keys().unwrap(), vec![48, 52, 55, 58, 62, 65, 69]);
+        assert!(Chord::new(124, ChordQuality::Major).keys().is_err());
+    }
+
+    #[test]
+    fn test_progressions() {
+        let c_major = Key::new(60, ScaleKind::Major);
+        let chords = c_major.progression("I vi ii7 V7 vii° IVmaj7").unwrap();
+        let expected = [
+            (60, ChordQuality::Major),
+            (69, ChordQuality::Minor),
+            (62, ChordQuality::Minor7),
+            (67, ChordQuality::Dominant7),
+            (71, ChordQuality::Diminished),
+            (65, ChordQuality::Major7),
+        ];
+        for (chord, (root, quality)) in chords.iter().zip(expected) {
+            assert_eq!((chord.root, chord.quality), (root, quality));
+        }
+        let a_minor = Key::new(57, ScaleKind::Minor);
+        let roots: Vec<u8> = a_minor.progression("i bVI III v").unwrap().iter().map(|c| c.root).collect();
+        assert_eq!(roots, vec![57, 65, 60, 64]);
+        let borrowed: Vec<u8> = a_minor.progression("i bVI bIII bVII").unwrap().iter().map(|c| c.root).collect();
+        assert_eq!(borrowed, vec![57, 65, 60, 67]);
+        assert_eq!(c_major.progression("bVII #iv°").unwrap().iter().map(|c| c.root).collect::<Vec<u8>>(), vec![70, 66]);
+        for bad in ["VIII", "Iv", "Ix", "vmaj9"] {
+            assert!(c_major.progression(bad).is_err(), "{}", bad);
+        }
+        assert!(Key::new(60, ScaleKind::Blues).progression("I").is_err());
+    }
+
+    #[test]
+    fn test_arpeggiator() {
+        let chord = [67, 60, 64];
+        let up = Arpeggiator::new(ArpMode::Up, 2, 8.0).unwrap();
+        let events = up.events(&chord, 1.0, 1.0).unwrap();
+        assert_eq!(keys(&events), vec![60, 64, 67, 72, 76, 79, 60, 64]);
+        assert_eq!(events[1].start, 1.125);
+        assert_eq!(events[1].duration, 0.0625);
+
+        let down = Arpeggiator::new(ArpMode::Down, 1, 4.0).unwrap();
+        assert_eq!(keys(&down.events(&chord, 0.0, 1.0).unwrap()), vec![67, 64, 60, 67]);
+        let up_down = Arpeggiator::new(ArpMode::UpDown, 1, 4.0).unwrap();
+        assert_eq!(keys(&up_down.events(&chord, 0.0, 1.5).unwrap()), vec![60, 64, 67, 64, 60, 64]);
+        let played = Arpeggiator::new(ArpMode::AsPlayed, 1, 3.0).unwrap();
+        assert_eq!(keys(&played.events(&chord, 0.0, 1.0).unwrap()), vec![67, 60, 64]);
+
+        let mut random = Arpeggiator::new(ArpMode::Random, 2, 16.0).unwrap();
+        random.seed = 7;
+        let first = random.events(&chord, 0.0, 2.0).unwrap();
+        assert_eq!(first, random.events(&chord, 0.0, 2.0).unwrap());
+        assert!(first.iter().all(|e| [60, 64, 67, 72, 76, 79].contains(&e.key)));
+        assert!(Arpeggiator::new(ArpMode::Up, 0, 4.0).is_err());
+    }
+
+    #[test]
+    fn test_invalid_arpeggios() {
+        let mut arp = Arpeggiator::new(ArpMode::Up, 1, 4.0).unwrap();
+        assert!(arp.events(&[60], f64::NAN, 1.0).is_err());
+        assert!(arp.events(&[60], 0.0, f64::INFINITY).is_err());
+        assert!(arp.events(&[60], 0.0, -1.0).is_err());
+        arp.gate = f64::NAN;
+        assert!(arp.events(&[60], 0.0, 1.0).is_err());
+        arp.gate = 0.5;
+        arp.rate = 0.0;
+        assert!(arp.events(&[60], 0.0, 1.0).is_err());
+        let chords = Key::new(60, ScaleKind::Major).progression("I").unwrap();
+        assert!(arp.progression(&chords, 1.0).is_err());
+        arp.rate = 4.0;
+        assert!(arp.progression(&chords, f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_arpeggiated_progression() {
+        let chords = Key::new(60, ScaleKind::Major).progression("I V").unwrap();
+        let arp = Arpeggiator::new(ArpMode::Up, 1, 3.0).unwrap();
+        let events = arp.progression(&chords, 1.0).unwrap();
+        assert_eq!(keys(&events), vec![60, 64, 67, 67, 71, 74]);
+        assert_eq!(events[3].start, 1.0);
+    }
+}
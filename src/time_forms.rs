@@ -5,15 +5,24 @@ use std::f32::consts::PI;
 use crate::render::Ugen;
 
 
-pub fn sine(config: &SynthConfig, t: u32, freq: f32, _bias: Option<f32>) -> f32 {
+pub fn sine(config: &SynthConfig, t: u32, freq: f32, bias: Option<f32>) -> f32 {
     let adjusted_freq = freq + config.tuning_offset_hz;
-    let phase = t as f32 * adjusted_freq * 2.0 * PI / config.sample_rate as f32;
-    (phase + config.phase_offset).sin() * config.amplitude_scaling
+    let phase = t as f32 * adjusted_freq / config.sample_rate as f32;
+    sine_phase(config, phase, adjusted_freq, bias)
+}
+
+pub fn sine_phase(config: &SynthConfig, phase: f32, _freq: f32, _bias: Option<f32>) -> f32 {
+    (phase * 2.0 * PI + config.phase_offset).sin() * config.amplitude_scaling
 }
 
 pub fn sawtooth(config: &SynthConfig, t: u32, freq: f32, bias: Option<f32>) -> f32 {
     let adjusted_freq = freq + config.tuning_offset_hz;
     let pos = (t as f32 * adjusted_freq % config.sample_rate as f32) / config.sample_rate as f32;
+    sawtooth_phase(config, pos, adjusted_freq, bias)
+}
+
+pub fn sawtooth_phase(config: &SynthConfig, phase: f32, _freq: f32, bias: Option<f32>) -> f32 {
+    let pos = phase.rem_euclid(1.0);
     let bias_val = bias.unwrap_or(0.5);
     2.0 * (pos - bias_val) * config.amplitude_scaling
 }
@@ -24,6 +33,12 @@ pub fn triangle(config: &SynthConfig, t: u32, freq: f32, _bias: Option<f32>) ->
     2.0 * phase.abs().rem_euclid(2.0) - 1.0
 }
 
+/// Period-1 triangle for phase-driven oscillators: -1 at phase 0, 1 at phase 0.5.
+pub fn triangle_phase(_config: &SynthConfig, phase: f32, _freq: f32, _bias: Option<f32>) -> f32 {
+    let pos = phase.rem_euclid(1.0);
+    1.0 - 4.0 * (pos - 0.5).abs()
+}
+
 pub fn render_test(config: &SynthConfig, ts: Vec<u32>, sr:u32, ugen: &Ugen) -> Vec<f32> {
     let mut samples: Vec<f32> = Vec::new();
     let freq: f32 = 400.0;
@@ -50,7 +65,7 @@ mod tests {
 
     #[test]
     fn test_sine() {
-        let config = SynthConfig::new(96000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0);
+        let config = SynthConfig::new(96000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0).unwrap();
         let epsilon = 1e-4;
 
         // Test at various points in the sine wave cycle
@@ -63,7 +78,7 @@ mod tests {
 
     #[test]
     fn test_sawtooth() {
-        let config = SynthConfig::new(96000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0);
+        let config = SynthConfig::new(96000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0).unwrap();
         let epsilon = 1e-4;
     
         assert_eq!(-1.0, sawtooth(&config, 0, 1.0, None));
@@ -74,4 +89,29 @@ mod tests {
         assert_eq!(-1.0, sawtooth(&config, 0, 2.0, None));
         assert_eq!(0.0, sawtooth(&config, 24000, 2.0, None));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_triangle() {
+        let config = SynthConfig::new(96000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0).unwrap();
+
+        assert_eq!(-1.0, triangle(&config, 0, 1.0, None));
+        assert_eq!(0.0, triangle(&config, 48000, 1.0, None));
+    }
+
+    #[test]
+    fn test_triangle_phase() {
+        let config = SynthConfig::new(96000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0).unwrap();
+
+        assert_eq!(-1.0, triangle_phase(&config, 0.0, 1.0, None));
+        assert_eq!(0.0, triangle_phase(&config, 0.25, 1.0, None));
+        assert_eq!(1.0, triangle_phase(&config, 0.5, 1.0, None));
+        assert_eq!(0.0, triangle_phase(&config, 0.75, 1.0, None));
+        // Wrapped phases repeat the first cycle.
+        for phase in [0.1, 0.3, 0.6, 0.9] {
+            let first = triangle_phase(&config, phase, 1.0, None);
+            for wrapped in [phase + 3.0, phase - 1.0] {
+                assert!((first - triangle_phase(&config, wrapped, 1.0, None)).abs() < 1e-4, "{}", wrapped);
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Pitch and tuning: MIDI notes and note names to frequency, equal divisions
+//! of the octave, just intonation, and Scala `.scl`/`.kbm` files.
+//! Key-to-degree mapping follows Scala's keyboard mapping semantics.
+
+use crate::error::{Error, Result};
+
+/// Equal-tempered frequency of `key` with A4 (key 69) at `a4` Hz.
+pub fn midi_to_frequency(key: f32, a4: f32) -> f32 {
+    a4 * 2f32.powf((key - 69.0) / 12.0)
+}
+
+/// Parses names like `C4`, `f#3`, `Bb-1` or `Ebb5` into MIDI keys, with C4 = 60.
+pub fn note_name_to_key(name: &str) -> Result<u8> {
+    let invalid = || Error::invalid(format!("Invalid note name {:?}", name));
+    let mut chars = name.trim().chars();
+    let letter = chars.next().ok_or_else(invalid)?;
+    let base = match letter.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return Err(invalid()),
+    };
+    let rest = chars.as_str();
+    let accidentals = rest.find(|c: char| c == '-' || c.is_ascii_digit()).ok_or_else(invalid)?;
+    let mut shift = 0i32;
+    for c in rest[..accidentals].chars() {
+        shift += match c {
+            '#' => 1,
+            'b' => -1,
+            _ => return Err(invalid()),
+        };
+    }
+    let octave: i32 = rest[accidentals..].parse().map_err(|_| invalid())?;
+    let key = (octave + 1) * 12 + base + shift;
+    u8::try_from(key).ok().filter(|&k| k < 128).ok_or_else(invalid)
+}
+
+/// Interval in cents of a frequency ratio.
+pub fn ratio_to_cents(ratio: f64) -> f64 {
+    1200.0 * ratio.log2()
+}
+
+/// Scale degrees in cents above the tonic. The last degree is the period,
+/// usually 1200 cents, as in a Scala file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scale {
+    pub description: String,
+    degrees: Vec<f64>,
+}
+
+impl Scale {
+    pub fn new(description: &str, degrees: Vec<f64>) -> Result<Scale> {
+        if degrees.is_empty() {
+            return Err(Error::invalid("A scale needs at least one degree"));
+        }
+        Ok(Scale { description: String::from(description), degrees })
+    }
+
+    /// `divisions` equal steps per octave.
+    pub fn equal(divisions: usize) -> Result<Scale> {
+        if divisions == 0 {
+            return Err(Error::invalid("An equal division needs at least one step"));
+        }
+        Ok(Scale::edo(divisions))
+    }
+
+    /// Equal division of the octave; `divisions` must be positive.
+    fn edo(divisions: usize) -> Scale {
+        let step = 1200.0 / divisions as f64;
+        Scale { description: format!("{}-EDO", divisions), degrees: (1..=divisions).map(|i| step * i as f64).collect() }
+    }
+
+    /// Just intonation from frequency ratios above the tonic, ending with the period (e.g. 2/1).
+    pub fn just(ratios: &[(u32, u32)]) -> Result<Scale> {
+        if ratios.iter().any(|&(n, d)| n == 0 || d == 0) {
+            return Err(Error::invalid("Ratios must be positive"));
+        }
+        Scale::new("just intonation", ratios.iter().map(|&(n, d)| ratio_to_cents(n as f64 / d as f64)).collect())
+    }
+
+    /// Parses the contents of a Scala `.scl` file.
+    pub fn parse_scl(text: &str) -> Result<Scale> {
+        let mut lines = text.lines().filter(|l| !l.starts_with('!'));
+        let description = lines.next().ok_or_else(|| Error::UnsupportedFormat(String::from("empty scl file")))?.trim();
+        let count: usize = lines.next()
+            .and_then(|l| l.split_whitespace().next())
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| Error::UnsupportedFormat(String::from("missing scl note count")))?;
+        let degrees = lines.take(count)
+            .map(|line| parse_pitch(line.split_whitespace().next().unwrap_or("")))
+            .collect::<Result<Vec<f64>>>()?;
+        if degrees.len() != count {
+            return Err(Error::UnsupportedFormat(format!("scl file lists {} of {} pitches", degrees.len(), count)));
+        }
+        Scale::new(description, degrees)
+    }
+
+    pub fn read_scl(filename: &str) -> Result<Scale> {
+        Scale::parse_scl(&std::fs::read_to_string(filename)?)
+    }
+
+    /// Cents of each degree above the tonic, ending with the period.
+    pub fn degrees(&self) -> &[f64] {
+        &self.degrees
+    }
+
+    pub fn len(&self) -> usize {
+        self.degrees.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.degrees.is_empty()
+    }
+
+    pub fn period(&self) -> f64 {
+        self.degrees[self.degrees.len() - 1]
+    }
+
+    /// Cents of any degree, wrapping through the period in both directions.
+    pub fn cents(&self, degree: i32) -> f64 {
+        let n = self.degrees.len() as i32;
+        let (periods, step) = (degree.div_euclid(n), degree.rem_euclid(n));
+        let within = if step == 0 { 0.0 } else { self.degrees[step as usize - 1] };
+        periods as f64 * self.period() + within
+    }
+}
+
+/// A Scala pitch: cents if it contains a period, otherwise a ratio or integer.
+fn parse_pitch(token: &str) -> Result<f64> {
+    let invalid = || Error::UnsupportedFormat(format!("invalid scl pitch {:?}", token));
+    if token.contains('.') {
+        return token.parse().map_err(|_| invalid());
+    }
+    let (n, d) = match token.split_once('/') {
+        Some((n, d)) => (n, d),
+        None => (token, "1"),
+    };
+    let n: f64 = n.parse().map_err(|_| invalid())?;
+    let d: f64 = d.parse().map_err(|_| invalid())?;
+    if n <= 0.0 || d <= 0.0 {
+        return Err(invalid());
+    }
+    Ok(ratio_to_cents(n / d))
+}
+
+/// Scala keyboard mapping (`.kbm`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyboardMap {
+    pub first_note: u8,
+    pub last_note: u8,
+    /// Key where the first entry of `mapping` (scale degree 0) sits.
+    pub middle_note: u8,
+    pub reference_note: u8,
+    pub reference_frequency: f64,
+    /// Scale degree that forms the mapping's repeat interval; 0 uses the scale period.
+    pub octave_degree: usize,
+    /// Scale degree per key in one repetition, `None` for unmapped keys.
+    /// An empty mapping maps keys to consecutive degrees.
+    pub mapping: Vec<Option<i32>>,
+}
+
+impl Default for KeyboardMap {
+    fn default() -> KeyboardMap {
+        KeyboardMap {
+            first_note: 0,
+            last_note: 127,
+            middle_note: 60,
+            reference_note: 69,
+            reference_frequency: 440.0,
+            octave_degree: 0,
+            mapping: Vec::new(),
+        }
+    }
+}
+
+impl KeyboardMap {
+    pub fn parse_kbm(text: &str) -> Result<KeyboardMap> {
+        let mut fields = text.lines()
+            .filter(|l| !l.starts_with('!'))
+            .map(|l| l.split_whitespace().next().unwrap_or(""));
+        let mut next = |what: &str| fields.next().ok_or_else(|| Error::UnsupportedFormat(format!("kbm file is missing {}", what)));
+        let invalid = |token: &str, what: &str| Error::UnsupportedFormat(format!("invalid kbm {} {:?}", what, token));
+        let integer = |token: &str, what: &str| -> Result<i64> { token.parse().map_err(|_| invalid(token, what)) };
+        let key = |token: &str, what: &str| -> Result<u8> {
+            u8::try_from(integer(token, what)?).ok().filter(|&k| k < 128).ok_or_else(|| invalid(token, what))
+        };
+        let token = next("size")?;
+        let size = usize::try_from(integer(token, "size")?).map_err(|_| invalid(token, "size"))?;
+        let first_note = key(next("first note")?, "first note")?;
+        let last_note = key(next("last note")?, "last note")?;
+        let middle_note = key(next("middle note")?, "middle note")?;
+        let reference_note = key(next("reference note")?, "reference note")?;
+        let token = next("reference frequency")?;
+        let reference_frequency = token.parse::<f64>().ok()
+            .filter(|f| f.is_finite() && *f > 0.0)
+            .ok_or_else(|| invalid(token, "reference frequency"))?;
+        let token = next("octave degree")?;
+        let octave_degree = usize::try_from(integer(token, "octave degree")?).map_err(|_| invalid(token, "octave degree"))?;
+        let mut mapping = Vec::with_capacity(size.min(128));
+        for _ in 0..size {
+            let token = next("mapping entries")?;
+            mapping.push(if token.eq_ignore_ascii_case("x") {
+                None
+            } else {
+                Some(i32::try_from(integer(token, "mapping entry")?).map_err(|_| invalid(token, "mapping entry"))?)
+            });
+        }
+        Ok(KeyboardMap { first_note, last_note, middle_note, reference_note, reference_frequency, octave_degree, mapping })
+    }
+
+    pub fn read_kbm(filename: &str) -> Result<KeyboardMap> {
+        KeyboardMap::parse_kbm(&std::fs::read_to_string(filename)?)
+    }
+}
+
+/// A scale laid out on the MIDI keyboard, with optional per-key cent offsets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tuning {
+    pub scale: Scale,
+    pub keyboard: KeyboardMap,
+    offsets: Vec<f64>,
+}
+
+impl Default for Tuning {
+    /// 12-tone equal temperament with A4 = 440 Hz.
+    fn default() -> Tuning {
+        Tuning::new(Scale::edo(12), KeyboardMap::default())
+    }
+}
+
+impl Tuning {
+    pub fn new(scale: Scale, keyboard: KeyboardMap) -> Tuning {
+        Tuning { scale, keyboard, offsets: vec![0.0; 128] }
+    }
+
+    /// Moves the reference so `key` sounds at `frequency`.
+    pub fn with_reference(mut self, key: u8, frequency: f64) -> Tuning {
+        self.keyboard.reference_note = key;
+        self.keyboard.reference_frequency = frequency;
+        self
+    }
+
+    /// Detunes a single key by `cents`.
+    pub fn set_offset(&mut self, key: u8, cents: f64) {
+        if let Some(offset) = self.offsets.get_mut(key as usize) {
+            *offset = cents;
+        }
+    }
+
+    /// Cents of `key` relative to the middle note, ignoring offsets.
+    fn key_cents(&self, key: u8) -> Option<f64> {
+        let map = &self.keyboard;
+        let offset = key as i32 - map.middle_note as i32;
+        if map.mapping.is_empty() {
+            return Some(self.scale.cents(offset));
+        }
+        let size = map.mapping.len() as i32;
+        let (repeats, index) = (offset.div_euclid(size), offset.rem_euclid(size));
+        let degree = map.mapping[index as usize]?;
+        let repeat = if map.octave_degree == 0 { self.scale.period() } else { self.scale.cents(map.octave_degree as i32) };
+        Some(repeats as f64 * repeat + self.scale.cents(degree))
+    }
+
+    /// Frequency of `key`. Fails if the keyboard map leaves the key or the
+    /// reference note unmapped.
+    pub fn frequency(&self, key: u8) -> Result<f32> {
+        let map = &self.keyboard;
+        let cents = Some(key)
+            .filter(|&k| k >= map.first_note && k <= map.last_note)
+            .and_then(|k| self.key_cents(k))
+            .ok_or_else(|| Error::invalid(format!("Key {} is not mapped", key)))?;
+        let reference = self.key_cents(map.reference_note)
+            .ok_or_else(|| Error::invalid(format!("Reference note {} is not mapped", map.reference_note)))?;
+        let offset = self.offsets.get(key as usize).copied().unwrap_or(0.0);
+        Ok((map.reference_frequency * 2f64.powf((cents + offset - reference) / 1200.0)) as f32)
+    }
+
+    pub fn frequency_of(&self, name: &str) -> Result<f32> {
+        self.frequency(note_name_to_key(name)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn test_note_names() {
+        assert_eq!(note_name_to_key("C4").unwrap(), 60);
+        assert_eq!(note_name_to_key("a4").unwrap(), 69);
+        assert_eq!(note_name_to_key("F#3").unwrap(), 54);
+        assert_eq!(note_name_to_key("Bb-1").unwrap(), 10);
+        assert_eq!(note_name_to_key("Cb4").unwrap(), 59);
+        assert!(note_name_to_key("H2").is_err());
+        assert!(note_name_to_key("C").is_err());
+        assert!(note_name_to_key("G9").unwrap() == 127 && note_name_to_key("G#9").is_err());
+    }
+
+    #[test]
+    fn test_equal_temperament() {
+        let tuning = Tuning::default();
+        assert!(close(tuning.frequency(69).unwrap(), 440.0));
+        assert!(close(tuning.frequency(60).unwrap(), midi_to_frequency(60.0, 440.0)));
+        assert!(close(tuning.frequency_of("A5").unwrap(), 880.0));
+
+        let baroque = Tuning::default().with_reference(69, 415.0);
+        assert!(close(baroque.frequency(57).unwrap(), 207.5));
+
+        let edo19 = Tuning::new(Scale::equal(19).unwrap(), KeyboardMap::default()).with_reference(60, 261.0);
+        assert!(close(edo19.frequency(79).unwrap(), 522.0));
+    }
+
+    #[test]
+    fn test_just_intonation_and_offsets() {
+        let scale = Scale::just(&[(9, 8), (5, 4), (4, 3), (3, 2), (5, 3), (15, 8), (2, 1)]).unwrap();
+        let map = KeyboardMap {
+            middle_note: 60,
+            reference_note: 60,
+            reference_frequency: 264.0,
+            mapping: vec![Some(0), None, Some(1), None, Some(2), Some(3), None, Some(4), None, Some(5), None, Some(6)],
+            ..KeyboardMap::default()
+        };
+        let mut tuning = Tuning::new(scale, map);
+        assert!(close(tuning.frequency(64).unwrap(), 330.0));
+        assert!(close(tuning.frequency(67).unwrap(), 396.0));
+        assert!(close(tuning.frequency(72).unwrap(), 528.0));
+        assert!(close(tuning.frequency(55).unwrap(), 198.0));
+        assert!(tuning.frequency(61).is_err());
+
+        tuning.set_offset(64, 1200.0);
+        assert!(close(tuning.frequency(64).unwrap(), 660.0));
+
+        tuning.keyboard.last_note = 255;
+        assert!(tuning.frequency(196).is_ok());
+        // Key 61 is unmapped, so nothing can be tuned relative to it.
+        tuning.keyboard.reference_note = 61;
+        assert!(tuning.frequency(60).is_err());
+    }
+
+    #[test]
+    fn test_scala_files() {
+        let scl = "! meanquar.scl\n!\n1/4-comma meantone scale. Pietro Aaron's temperament (1523)\n 12\n!\n 76.04900\n 193.15686\n 310.26471\n 5/4\n 503.42157\n 579.47057\n 696.57843\n 25/16\n 889.73529\n 1006.84314\n 1082.89214\n 2/1\n";
+        let scale = Scale::parse_scl(scl).unwrap();
+        assert_eq!(scale.len(), 12);
+        assert!((scale.degrees()[3] - ratio_to_cents(1.25)).abs() < 1e-9);
+        assert_eq!(scale.period(), 1200.0);
+        assert!(scale.description.starts_with("1/4-comma"));
+
+        let kbm = "! example.kbm\n12\n0\n127\n60\n69\n440.0\n0\n! mapping\n0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\nx\n";
+        let map = KeyboardMap::parse_kbm(kbm).unwrap();
+        assert_eq!(map.mapping.len(), 12);
+        assert_eq!(map.mapping[11], None);
+        let tuning = Tuning::new(scale, map);
+        assert!(close(tuning.frequency(69).unwrap(), 440.0));
+        assert!(tuning.frequency(71).is_err());
+
+        assert!(Scale::parse_scl("bad\n3\n100.0\n").is_err());
+        assert!(Scale::equal(0).is_err());
+        assert!(KeyboardMap::parse_kbm("0\n0\n300\n60\n69\n440.0\n0\n").is_err());
+        assert!(KeyboardMap::parse_kbm("0\n0\n127\n-4\n69\n440.0\n0\n").is_err());
+        assert!(KeyboardMap::parse_kbm("0\n0\n127\n60\n69\n440.0\n1.5\n").is_err());
+    }
+}
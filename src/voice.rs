@@ -0,0 +1,401 @@
+//! Fixed-size voice pool for live-style note events.
+//! Note-ons are assigned to voices with a configurable stealing policy in
+//! polyphonic mode, or to a single voice with note priority and optional
+//! legato in mono mode. CPU cost is bounded by the pool size.
+
+use crate::error::{Error, Result};
+use crate::oscillator::Oscillator;
+use crate::render::PhaseUgen;
+use crate::synth_config::SynthConfig;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StealPolicy {
+    /// Take the voice that was triggered longest ago.
+    Oldest,
+    /// Take the voice with the lowest envelope level.
+    Quietest,
+    /// Retrigger a voice already playing the same key, otherwise take the oldest.
+    SameNote,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NotePriority {
+    Last,
+    Low,
+    High,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Poly { voices: usize, steal: StealPolicy },
+    /// Single voice; with `legato` set, changing notes while one is held keeps
+    /// the envelope running instead of retriggering it.
+    Mono { priority: NotePriority, legato: bool },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stage {
+    Idle,
+    Attack,
+    Sustain,
+    Release,
+}
+
+#[derive(Clone, Copy)]
+pub struct Voice {
+    oscillator: Oscillator,
+    key: u8,
+    frequency: f32,
+    velocity: f32,
+    level: f32,
+    stage: Stage,
+    /// Order of the last trigger, used for oldest-first stealing.
+    age: u64,
+}
+
+impl Voice {
+    fn new(ugen: PhaseUgen) -> Voice {
+        Voice {
+            oscillator: Oscillator::new(ugen, Some(0.5)),
+            key: 0,
+            frequency: 0.0,
+            velocity: 0.0,
+            level: 0.0,
+            stage: Stage::Idle,
+            age: 0,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.stage != Stage::Idle
+    }
+
+    pub fn is_held(&self) -> bool {
+        matches!(self.stage, Stage::Attack | Stage::Sustain)
+    }
+
+    pub fn key(&self) -> Option<u8> {
+        if self.is_active() { Some(self.key) } else { None }
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn phase(&self) -> f64 {
+        self.oscillator.phase()
+    }
+
+    /// Starts the attack from the current level, so stolen voices do not click.
+    fn trigger(&mut self, key: u8, frequency: f32, velocity: f32, age: u64) {
+        if !self.is_active() {
+            self.oscillator.reset();
+        }
+        self.key = key;
+        self.frequency = frequency;
+        self.velocity = velocity;
+        self.stage = Stage::Attack;
+        self.age = age;
+    }
+
+    fn next_sample(&mut self, config: &SynthConfig, attack_step: f32, release_step: f32) -> f32 {
+        match self.stage {
+            Stage::Idle => return 0.0,
+            Stage::Attack => {
+                self.level = (self.level + attack_step).min(1.0);
+                if self.level >= 1.0 {
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => {}
+            Stage::Release => {
+                self.level = (self.level - release_step).max(0.0);
+                if self.level <= 0.0 {
+                    self.stage = Stage::Idle;
+                }
+            }
+        }
+        self.velocity * self.level * self.oscillator.next_sample(config, self.frequency)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventKind {
+    NoteOn { key: u8, frequency: f32, velocity: f32 },
+    NoteOff { key: u8 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoiceEvent {
+    /// Frame at which the event takes effect.
+    pub frame: usize,
+    pub kind: EventKind,
+}
+
+pub struct VoiceAllocator {
+    voices: Vec<Voice>,
+    mode: Mode,
+    /// Keys currently held in mono mode, oldest first.
+    held: Vec<(u8, f32, f32)>,
+    counter: u64,
+    attack_step: f32,
+    release_step: f32,
+}
+
+impl VoiceAllocator {
+    /// `attack` and `release` are linear ramp times in seconds.
+    pub fn new(config: &SynthConfig, ugen: PhaseUgen, mode: Mode, attack: f32, release: f32) -> Result<VoiceAllocator> {
+        let size = match mode {
+            Mode::Poly { voices: 0, .. } => return Err(Error::invalid("Polyphonic mode needs at least one voice")),
+            Mode::Poly { voices, .. } => voices,
+            Mode::Mono { .. } => 1,
+        };
+        if !attack.is_finite() || attack < 0.0 || !release.is_finite() || release < 0.0 {
+            return Err(Error::invalid("Attack and release must be finite and non-negative"));
+        }
+        let step = |seconds: f32| if seconds > 0.0 { 1.0 / (seconds * config.sample_rate as f32) } else { 1.0 };
+        Ok(VoiceAllocator {
+            voices: vec![Voice::new(ugen); size],
+            mode,
+            held: Vec::new(),
+            counter: 0,
+            attack_step: step(attack),
+            release_step: step(release),
+        })
+    }
+
+    pub fn voices(&self) -> &[Voice] {
+        &self.voices
+    }
+
+    pub fn active_voices(&self) -> usize {
+        self.voices.iter().filter(|v| v.is_active()).count()
+    }
+
+    fn steal(&self, key: u8, policy: StealPolicy) -> usize {
+        if policy == StealPolicy::SameNote {
+            if let Some(i) = self.voices.iter().position(|v| v.key() == Some(key)) {
+                return i;
+            }
+        }
+        if let Some(i) = self.voices.iter().position(|v| !v.is_active()) {
+            return i;
+        }
+        let candidates = self.voices.iter().enumerate();
+        let chosen = match policy {
+            StealPolicy::Quietest => candidates.min_by(|a, b| a.1.level.total_cmp(&b.1.level)),
+            StealPolicy::Oldest | StealPolicy::SameNote => candidates.min_by_key(|(_, v)| v.age),
+        };
+        chosen.map(|(i, _)| i).unwrap_or(0)
+    }
+
+    fn mono_target(&self, priority: NotePriority) -> Option<(u8, f32, f32)> {
+        match priority {
+            NotePriority::Last => self.held.last().copied(),
+            NotePriority::Low => self.held.iter().min_by_key(|n| n.0).copied(),
+            NotePriority::High => self.held.iter().max_by_key(|n| n.0).copied(),
+        }
+    }
+
+    /// Moves the mono voice to the prioritized held key.
+    fn mono_update(&mut self, priority: NotePriority, legato: bool) {
+        self.counter += 1;
+        let target = self.mono_target(priority);
+        let voice = &mut self.voices[0];
+        match target {
+            None => {
+                if voice.is_active() {
+                    voice.stage = Stage::Release;
+                }
+            }
+            Some((key, frequency, velocity)) => {
+                if voice.is_held() && voice.key == key {
+                    return;
+                }
+                if legato && voice.is_held() {
+                    voice.key = key;
+                    voice.frequency = frequency;
+                } else {
+                    voice.trigger(key, frequency, velocity, self.counter);
+                }
+            }
+        }
+    }
+
+    pub fn note_on(&mut self, key: u8, frequency: f32, velocity: f32) -> Result<()> {
+        if !frequency.is_finite() || frequency < 0.0 || !(0.0..=1.0).contains(&velocity) {
+            return Err(Error::invalid(format!("Invalid note {} at {} Hz, velocity {}", key, frequency, velocity)));
+        }
+        match self.mode {
+            Mode::Poly { steal, .. } => {
+                self.counter += 1;
+                let i = self.steal(key, steal);
+                self.voices[i].trigger(key, frequency, velocity, self.counter);
+            }
+            Mode::Mono { priority, legato } => {
+                self.held.retain(|n| n.0 != key);
+                self.held.push((key, frequency, velocity));
+                self.mono_update(priority, legato);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn note_off(&mut self, key: u8) {
+        match self.mode {
+            Mode::Poly { .. } => {
+                for voice in self.voices.iter_mut().filter(|v| v.is_held() && v.key == key) {
+                    voice.stage = Stage::Release;
+                }
+            }
+            Mode::Mono { priority, legato } => {
+                self.held.retain(|n| n.0 != key);
+                self.mono_update(priority, legato);
+            }
+        }
+    }
+
+    pub fn apply(&mut self, event: &EventKind) -> Result<()> {
+        match *event {
+            EventKind::NoteOn { key, frequency, velocity } => self.note_on(key, frequency, velocity)?,
+            EventKind::NoteOff { key } => self.note_off(key),
+        }
+        Ok(())
+    }
+
+    /// Mixes all voices into `out`.
+    pub fn process(&mut self, config: &SynthConfig, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = self.voices.iter_mut()
+                .map(|v| v.next_sample(config, self.attack_step, self.release_step))
+                .sum();
+        }
+    }
+
+    /// Renders `frames` frames, applying each event at its frame.
+    /// Events must be sorted by frame.
+    pub fn render(&mut self, config: &SynthConfig, events: &[VoiceEvent], frames: usize) -> Result<Vec<f32>> {
+        let mut out = vec![0.0; frames];
+        let mut pos = 0;
+        for event in events {
+            let at = event.frame.clamp(pos, frames);
+            self.process(config, &mut out[pos..at]);
+            self.apply(&event.kind)?;
+            pos = at;
+        }
+        self.process(config, &mut out[pos..]);
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_forms;
+
+    fn test_config() -> SynthConfig {
+        SynthConfig::new(1000, 20.0, 20000.0, 1.0, 0.0, 0.0, 1.0).unwrap()
+    }
+
+    fn allocator(mode: Mode) -> VoiceAllocator {
+        VoiceAllocator::new(&test_config(), time_forms::sine_phase, mode, 0.01, 0.01).unwrap()
+    }
+
+    fn keys(allocator: &VoiceAllocator) -> Vec<Option<u8>> {
+        allocator.voices().iter().map(|v| v.key()).collect()
+    }
+
+    #[test]
+    fn test_steal_oldest() {
+        let mut a = allocator(Mode::Poly { voices: 2, steal: StealPolicy::Oldest });
+        a.note_on(60, 261.6, 1.0).unwrap();
+        a.note_on(64, 329.6, 1.0).unwrap();
+        a.note_on(67, 392.0, 1.0).unwrap();
+        assert_eq!(keys(&a), vec![Some(67), Some(64)]);
+        assert_eq!(a.active_voices(), 2);
+    }
+
+    #[test]
+    fn test_steal_quietest_and_same_note() {
+        let config = test_config();
+        let mut a = allocator(Mode::Poly { voices: 2, steal: StealPolicy::Quietest });
+        a.note_on(60, 261.6, 1.0).unwrap();
+        a.process(&config, &mut [0.0; 20]);
+        a.note_on(64, 329.6, 1.0).unwrap();
+        a.process(&config, &mut [0.0; 2]);
+        a.note_on(67, 392.0, 1.0).unwrap();
+        assert_eq!(keys(&a), vec![Some(60), Some(67)]);
+
+        let mut a = allocator(Mode::Poly { voices: 3, steal: StealPolicy::SameNote });
+        a.note_on(60, 261.6, 1.0).unwrap();
+        a.note_on(64, 329.6, 1.0).unwrap();
+        a.note_on(60, 261.6, 0.5).unwrap();
+        assert_eq!(keys(&a), vec![Some(60), Some(64), None]);
+    }
+
+    #[test]
+    fn test_mono_priority() {
+        let mut a = allocator(Mode::Mono { priority: NotePriority::Low, legato: false });
+        a.note_on(64, 329.6, 1.0).unwrap();
+        a.note_on(60, 261.6, 1.0).unwrap();
+        a.note_on(67, 392.0, 1.0).unwrap();
+        assert_eq!(keys(&a), vec![Some(60)]);
+        a.note_off(60);
+        assert_eq!(keys(&a), vec![Some(64)]);
+
+        let mut a = allocator(Mode::Mono { priority: NotePriority::High, legato: false });
+        a.note_on(64, 329.6, 1.0).unwrap();
+        a.note_on(60, 261.6, 1.0).unwrap();
+        assert_eq!(keys(&a), vec![Some(64)]);
+
+        let mut a = allocator(Mode::Mono { priority: NotePriority::Last, legato: false });
+        a.note_on(64, 329.6, 1.0).unwrap();
+        a.note_on(60, 261.6, 1.0).unwrap();
+        assert_eq!(keys(&a), vec![Some(60)]);
+        a.note_off(60);
+        assert_eq!(keys(&a), vec![Some(64)]);
+        a.note_off(64);
+        assert!(!a.voices()[0].is_held());
+    }
+
+    #[test]
+    fn test_legato_keeps_phase_and_envelope() {
+        let config = test_config();
+        let mut a = allocator(Mode::Mono { priority: NotePriority::Last, legato: true });
+        a.note_on(60, 100.0, 1.0).unwrap();
+        a.process(&config, &mut [0.0; 33]);
+        let before = (a.voices()[0].phase(), a.voices()[0].level());
+        a.note_on(62, 112.2, 1.0).unwrap();
+        let voice = a.voices()[0];
+        assert_eq!((voice.phase(), voice.level()), before);
+        assert_eq!(voice.stage, Stage::Sustain);
+        assert_eq!(voice.key(), Some(62));
+    }
+
+    #[test]
+    fn test_render_events() {
+        let config = test_config();
+        let mut a = allocator(Mode::Poly { voices: 4, steal: StealPolicy::Oldest });
+        let events = [
+            VoiceEvent { frame: 0, kind: EventKind::NoteOn { key: 60, frequency: 100.0, velocity: 0.5 } },
+            VoiceEvent { frame: 100, kind: EventKind::NoteOff { key: 60 } },
+        ];
+        let out = a.render(&config, &events, 200).unwrap();
+        assert!(out[50..100].iter().any(|x| x.abs() > 0.4));
+        assert!(out[111..].iter().all(|&x| x == 0.0));
+        assert_eq!(a.active_voices(), 0);
+    }
+
+    #[test]
+    fn test_invalid_voice_input() {
+        let config = test_config();
+        let mode = Mode::Poly { voices: 2, steal: StealPolicy::Oldest };
+        assert!(VoiceAllocator::new(&config, time_forms::sine_phase, mode, -0.01, 0.01).is_err());
+        assert!(VoiceAllocator::new(&config, time_forms::sine_phase, mode, 0.01, f32::NAN).is_err());
+        let mut a = allocator(mode);
+        assert!(a.note_on(60, f32::NAN, 1.0).is_err());
+        assert!(a.note_on(60, -261.6, 1.0).is_err());
+        assert!(a.note_on(60, 261.6, 2.0).is_err());
+        assert_eq!(a.active_voices(), 0);
+    }
+}
@@ -0,0 +1,289 @@
+//! WAV output at a chosen sample format, with dither and noise shaping
+//! applied when quantizing to integer formats, and WAV import into float buffers.
+
+use std::io::Read;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use crate::buffer::AudioBuffer;
+use crate::error::{Error, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WavFormat {
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl WavFormat {
+    pub fn bits_per_sample(&self) -> u16 {
+        match self {
+            WavFormat::Int16 => 16,
+            WavFormat::Int24 => 24,
+            WavFormat::Int32 | WavFormat::Float32 => 32,
+        }
+    }
+
+    pub fn spec(&self, channels: u16, sample_rate: u32) -> hound::WavSpec {
+        hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: self.bits_per_sample(),
+            sample_format: match self {
+                WavFormat::Float32 => hound::SampleFormat::Float,
+                _ => hound::SampleFormat::Int,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dither {
+    None,
+    /// Uniform noise of one LSB peak to peak.
+    Rectangular,
+    /// Sum of two rectangular sources, two LSB peak to peak.
+    Triangular,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseShaping {
+    None,
+    /// Feeds back the previous quantization error, pushing noise up in frequency.
+    FirstOrder,
+    /// Second-order error feedback, `2e[n-1] - e[n-2]`.
+    SecondOrder,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WavOptions {
+    pub format: WavFormat,
+    pub dither: Dither,
+    pub noise_shaping: NoiseShaping,
+    /// Seed for the dither noise, so renders are reproducible.
+    pub seed: u64,
+}
+
+impl WavOptions {
+    pub fn new(format: WavFormat) -> WavOptions {
+        let dither = match format {
+            WavFormat::Float32 => Dither::None,
+            _ => Dither::Triangular,
+        };
+        WavOptions { format, dither, noise_shaping: NoiseShaping::None, seed: 0 }
+    }
+}
+
+impl Default for WavOptions {
+    fn default() -> WavOptions {
+        WavOptions::new(WavFormat::Float32)
+    }
+}
+
+/// Converts float samples to integers of a given bit depth.
+/// Keeps per-channel error history for noise shaping.
+pub struct Quantizer {
+    bits: u16,
+    dither: Dither,
+    noise_shaping: NoiseShaping,
+    rng: StdRng,
+    errors: Vec<[f32; 2]>,
+}
+
+impl Quantizer {
+    pub fn new(bits: u16, channels: u16, options: &WavOptions) -> Result<Quantizer> {
+        if !(2..=32).contains(&bits) {
+            return Err(Error::UnsupportedFormat(format!("{} bit integer samples", bits)));
+        }
+        Ok(Quantizer {
+            bits,
+            dither: options.dither,
+            noise_shaping: options.noise_shaping,
+            rng: StdRng::seed_from_u64(options.seed),
+            errors: vec![[0.0; 2]; channels as usize],
+        })
+    }
+
+    fn dither_noise(&mut self) -> f64 {
+        match self.dither {
+            Dither::None => 0.0,
+            Dither::Rectangular => self.rng.gen_range(-0.5..0.5),
+            Dither::Triangular => self.rng.gen_range(-0.5..0.5) + self.rng.gen_range(-0.5..0.5),
+        }
+    }
+
+    /// Scales a sample in `[-1, 1]` to the integer range, clipping anything outside.
+    pub fn quantize(&mut self, x: f32, channel: usize) -> i32 {
+        let full_scale = (1i64 << (self.bits - 1)) as f64;
+        let [e1, e2] = self.errors[channel];
+        let feedback = match self.noise_shaping {
+            NoiseShaping::None => 0.0,
+            NoiseShaping::FirstOrder => e1,
+            NoiseShaping::SecondOrder => 2.0 * e1 - e2,
+        };
+        let target = x as f64 * full_scale - feedback as f64;
+        let noise = self.dither_noise();
+        let rounded = (target + noise).round();
+        // Only the rounding error is shaped; feeding back clipping error would
+        // release a full-scale spike once the signal drops back into range.
+        let error = (rounded - target) as f32;
+        self.errors[channel] = [error, e1];
+        rounded.clamp(-full_scale, full_scale - 1.0) as i32
+    }
+}
+
+/// Writes `buffer` to `filename` in the requested format.
+pub fn write_wav(buffer: &AudioBuffer, filename: &str, options: &WavOptions) -> Result<()> {
+    let spec = options.format.spec(buffer.channels, buffer.sample_rate);
+    let mut writer = hound::WavWriter::create(filename, spec)?;
+    match options.format {
+        WavFormat::Float32 => {
+            for &sample in &buffer.samples {
+                writer.write_sample(sample)?;
+            }
+        }
+        format => {
+            let mut quantizer = Quantizer::new(format.bits_per_sample(), buffer.channels, options)?;
+            for (i, &sample) in buffer.samples.iter().enumerate() {
+                let channel = i % buffer.channels as usize;
+                writer.write_sample(quantizer.quantize(sample, channel))?;
+            }
+        }
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+/// Decodes WAV data into a float buffer with samples in `[-1, 1]`.
+/// Integer formats from 8 to 32 bits and 32-bit float are supported.
+/// With `downmix` set, all channels are averaged into one.
+pub fn read_wav_from<R: Read>(reader: R, downmix: bool) -> Result<AudioBuffer> {
+    let mut reader = hound::WavReader::new(reader)?;
+    let spec = reader.spec();
+    let samples = match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Float, 32) => reader.samples::<f32>().collect::<std::result::Result<Vec<f32>, _>>()?,
+        (hound::SampleFormat::Int, bits @ 8..=32) => {
+            let full_scale = (1i64 << (bits - 1)) as f32;
+            reader.samples::<i32>()
+                .map(|s| s.map(|v| v as f32 / full_scale))
+                .collect::<std::result::Result<Vec<f32>, _>>()?
+        }
+        (format, bits) => {
+            return Err(Error::UnsupportedFormat(format!("{} bit {:?} samples", bits, format)));
+        }
+    };
+    let buffer = AudioBuffer { samples, channels: spec.channels, sample_rate: spec.sample_rate };
+    Ok(if downmix { buffer.to_mono() } else { buffer })
+}
+
+/// Reads a WAV file; see `read_wav_from`.
+pub fn read_wav(filename: &str, downmix: bool) -> Result<AudioBuffer> {
+    read_wav_from(std::fs::File::open(filename)?, downmix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn undithered(format: WavFormat) -> WavOptions {
+        WavOptions { dither: Dither::None, ..WavOptions::new(format) }
+    }
+
+    #[test]
+    fn test_scaling_and_clipping() {
+        let mut q = Quantizer::new(16, 1, &undithered(WavFormat::Int16)).unwrap();
+        assert_eq!(q.quantize(0.0, 0), 0);
+        assert_eq!(q.quantize(0.5, 0), 16384);
+        assert_eq!(q.quantize(1.0, 0), 32767);
+        assert_eq!(q.quantize(-1.0, 0), -32768);
+        assert_eq!(q.quantize(-3.0, 0), -32768);
+
+        let mut q = Quantizer::new(24, 1, &undithered(WavFormat::Int24)).unwrap();
+        assert_eq!(q.quantize(1.0, 0), (1 << 23) - 1);
+    }
+
+    #[test]
+    fn test_tpdf_dither_bounds() {
+        let options = WavOptions::new(WavFormat::Int16);
+        let mut q = Quantizer::new(16, 1, &options).unwrap();
+        let x = 100.3 / 32768.0;
+        let values: Vec<i32> = (0..1000).map(|_| q.quantize(x, 0)).collect();
+        assert!(values.iter().all(|&v| (99..=102).contains(&v)));
+        let mean = values.iter().sum::<i32>() as f32 / values.len() as f32;
+        assert!((mean - 100.3).abs() < 0.1, "{}", mean);
+    }
+
+    #[test]
+    fn test_dither_is_seeded() {
+        let options = WavOptions { noise_shaping: NoiseShaping::SecondOrder, ..WavOptions::new(WavFormat::Int16) };
+        let run = || {
+            let mut q = Quantizer::new(16, 1, &options).unwrap();
+            (0..64).map(|i| q.quantize((i as f32 * 0.1).sin() * 0.01, 0)).collect::<Vec<i32>>()
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn test_noise_shaping_preserves_mean() {
+        let options = WavOptions { dither: Dither::None, noise_shaping: NoiseShaping::FirstOrder, ..WavOptions::new(WavFormat::Int16) };
+        let mut q = Quantizer::new(16, 1, &options).unwrap();
+        let x = 10.25 / 32768.0;
+        let sum: i32 = (0..400).map(|_| q.quantize(x, 0)).sum();
+        assert!((sum as f32 / 400.0 - 10.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_noise_shaping_recovers_from_clipping() {
+        for noise_shaping in [NoiseShaping::FirstOrder, NoiseShaping::SecondOrder] {
+            let options = WavOptions { dither: Dither::None, noise_shaping, ..WavOptions::new(WavFormat::Int16) };
+            let mut q = Quantizer::new(16, 1, &options).unwrap();
+            for _ in 0..100 {
+                assert_eq!(q.quantize(1.2, 0), 32767);
+            }
+            let silence: Vec<i32> = (0..10).map(|_| q.quantize(0.0, 0)).collect();
+            assert!(silence.iter().all(|v| v.abs() <= 2), "{:?} {:?}", noise_shaping, silence);
+        }
+    }
+
+    fn encode(buffer: &AudioBuffer, format: WavFormat) -> Vec<u8> {
+        let options = WavOptions { dither: Dither::None, ..WavOptions::new(format) };
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let mut source = crate::stream::BufferSource::new(buffer);
+        crate::stream::render_to_writer(&mut source, &mut cursor, &options, 64).unwrap();
+        cursor.into_inner()
+    }
+
+    #[test]
+    fn test_read_round_trip() {
+        let left: Vec<f32> = (0..200).map(|i| (i as f32 * 0.05).sin() * 0.9).collect();
+        let right: Vec<f32> = left.iter().map(|x| -x * 0.5).collect();
+        let buffer = AudioBuffer::from_channels(&[left, right], 22050).unwrap();
+        for (format, tolerance) in [(WavFormat::Int16, 1e-4), (WavFormat::Int24, 1e-6), (WavFormat::Int32, 1e-6), (WavFormat::Float32, 0.0)] {
+            let decoded = read_wav_from(&encode(&buffer, format)[..], false).unwrap();
+            assert_eq!(decoded.channels, 2);
+            assert_eq!(decoded.sample_rate, 22050);
+            assert_eq!(decoded.frames(), 200);
+            for (a, b) in decoded.samples.iter().zip(&buffer.samples) {
+                assert!((a - b).abs() <= tolerance, "{:?}: {} {}", format, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_8_bit_and_downmix() {
+        let spec = hound::WavSpec { channels: 2, sample_rate: 8000, bits_per_sample: 8, sample_format: hound::SampleFormat::Int };
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut cursor, spec).unwrap();
+        for sample in [64i8, 0, -128, -64] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        let bytes = cursor.into_inner();
+
+        let stereo = read_wav_from(&bytes[..], false).unwrap();
+        assert_eq!(stereo.samples, vec![0.5, 0.0, -1.0, -0.5]);
+        let mono = read_wav_from(&bytes[..], true).unwrap();
+        assert_eq!(mono.channels, 1);
+        assert_eq!(mono.samples, vec![0.25, -0.75]);
+    }
+}
@@ -1,11 +1,22 @@
+#![allow(dead_code)]
 const TEST_AUDIO_DIR: &str = "test-render";
 use raudio_synth::synth_config::SynthConfig;
 
 pub fn test_audio_name(config:&SynthConfig, label:&str) -> String {
-    let name: String = format!("{}_sample-rate_{}_channels_{}", label, config.sample_rate, 1);
+    test_audio_name_channels(config, label, 1)
+}
+
+pub fn test_audio_name_channels(config:&SynthConfig, label:&str, channels: u16) -> String {
+    std::fs::create_dir_all(TEST_AUDIO_DIR).unwrap();
+    let name: String = format!("{}_sample-rate_{}_channels_{}", label, config.sample_rate, channels);
     format!("{}/{}.wav", TEST_AUDIO_DIR, name)
 }
 
+pub fn test_midi_name(label:&str) -> String {
+    std::fs::create_dir_all(TEST_AUDIO_DIR).unwrap();
+    format!("{}/{}.mid", TEST_AUDIO_DIR, label)
+}
+
 
 // Define a basic SynthConfig for testing
 pub fn test_config() -> SynthConfig {
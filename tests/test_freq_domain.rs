@@ -12,8 +12,8 @@ fn test_write_freq_forms() {
     shapes_map.insert(String::from("sine"), raudio_synth::freq_forms::sine);
 
     for (name, func) in &shapes_map {
-        let label = common::test_audio_name(&config, &format!("time_form_{}", name));
-        let filename = raudio_synth::render::render_ugen(&config, func, &label);
+        let label = common::test_audio_name(config, &format!("time_form_{}", name));
+        let filename = raudio_synth::render::render_ugen(config, func, &label).unwrap();
         println!("Completed writing test waveform {}", filename);
 
     }
@@ -0,0 +1,29 @@
+mod common;
+
+use raudio_synth::midi::MidiFile;
+use raudio_synth::sequence::{Instrument, Note, Timeline, UgenInstrument};
+
+#[test]
+fn test_midi_export_and_render() {
+    let config = common::test_config();
+    let melody = [400.0, 600.0, 500.0, 700.0, 800.0, 600.0, 500.0, 400.0];
+    let mut timeline = Timeline::default();
+    for (index, &frequency) in melody.iter().enumerate() {
+        timeline.push(Note::new(index as f64 * 0.25, 0.25, frequency, 0.6, 0));
+    }
+    timeline.push(Note::new(0.0, 2.0, 130.81, 0.4, 1));
+
+    let filename = common::test_midi_name("melody-export");
+    MidiFile::from_timeline(&timeline, &config, 4.0, 480).unwrap().write(&filename).unwrap();
+
+    let midi = MidiFile::read(&filename).unwrap();
+    let lead = UgenInstrument::new(raudio_synth::time_forms::sine_phase, 0.005, 0.1);
+    let bass = UgenInstrument::new(raudio_synth::freq_forms::sawtooth_phase, 0.02, 0.3);
+    let instruments: [&dyn Instrument; 2] = [&lead, &bass];
+    let buffer = midi.render(&config, &instruments, &|channel, _| Some(channel as usize)).unwrap();
+    assert_eq!(midi.to_timeline(&|channel, _| Some(channel as usize)).notes.len(), 9);
+
+    let wav = common::test_audio_name(&config, "midi-render");
+    buffer.write_wav(&wav).unwrap();
+    println!("Completed writing test waveform {}", wav);
+}
@@ -2,13 +2,21 @@ mod common;
 
 use rand::seq::SliceRandom;
 use rand::thread_rng;
+use raudio_synth::buffer::AudioBuffer;
+use raudio_synth::pattern::{self, Pattern};
+use raudio_synth::pitch::{Glide, GlideCurve, GlideMode, PitchedNote};
+use raudio_synth::sequence::{Note, Timeline, UgenInstrument};
 use raudio_synth::synth_config::SynthConfig;
+use raudio_synth::tempo::TempoMap;
+use raudio_synth::theory::{ArpMode, Arpeggiator, Key, ScaleKind};
+use raudio_synth::tuning::Tuning;
+use raudio_synth::wav::{WavFormat, WavOptions};
 
 #[test]
 fn test_write_sequenced_melody() {
     let config = &common::test_config();
     let melody = [400.0, 600.0, 500.0, 700.0, 800.0, 600.0, 500.0, 400.0];
-    let waveform_functions = vec![
+    let waveform_functions = [
         raudio_synth::freq_forms::sawtooth,
         raudio_synth::freq_forms::triangle,
         raudio_synth::freq_forms::sine,
@@ -20,7 +28,7 @@ fn test_write_sequenced_melody() {
     for (index, &frequency) in melody.iter().enumerate() {
         let ugen = waveform_functions.choose(&mut rng).unwrap();
         let note_duration = index + 1;
-        let mut sequence = Vec::with_capacity(note_duration as usize);
+        let mut sequence = Vec::with_capacity(note_duration);
         let num_samples = (config.sample_rate as f32 * note_duration as f32 / config.cps).floor() as i32;
         for i in 0..num_samples {
             let sample = ugen(config, i as u32, frequency, None);
@@ -31,23 +39,69 @@ fn test_write_sequenced_melody() {
     }
 
     let label = "melody-test";
-    write_sequence_to_file(&config, &complete_sequence, label);
+    write_sequence_to_file(config, &complete_sequence, label);
 }
 
+#[test]
+fn test_write_glide_melody() {
+    let config = &common::test_config();
+    let melody = [400.0, 600.0, 500.0, 700.0, 800.0, 600.0, 500.0, 400.0];
+    let notes: Vec<PitchedNote> = melody.iter()
+        .map(|&frequency| PitchedNote::new(frequency, 0.5 / config.cps))
+        .collect();
+    let glide = Glide::new(GlideMode::Time(0.08), GlideCurve::Exponential).unwrap();
+    let sequence = raudio_synth::pitch::render_legato(config, raudio_synth::time_forms::sine_phase, &notes, Some(glide)).unwrap();
 
-fn write_sequence_to_file(config: &SynthConfig, sequence: &[f32], label: &str) {
-    let spec = hound::WavSpec {
-        channels: 1,
-        sample_rate: config.sample_rate,
-        bits_per_sample: 32,
-        sample_format: hound::SampleFormat::Int,
-    };
-    let filename = common::test_audio_name(&config, label);
-    let mut writer = hound::WavWriter::create(filename.clone(), spec).unwrap();
-
-    for &sample in sequence {
-        writer.write_sample(sample).unwrap(); 
+    write_sequence_to_file(config, &sequence, "glide-melody-test");
+}
+
+#[test]
+fn test_write_timeline_melody() {
+    let config = &common::test_config();
+    let melody = [400.0, 600.0, 500.0, 700.0, 800.0, 600.0, 500.0, 400.0];
+    let lead = UgenInstrument::new(raudio_synth::time_forms::sine_phase, 0.01, 0.3);
+    let pad = UgenInstrument::new(raudio_synth::freq_forms::triangle_phase, 0.2, 0.8);
+
+    let mut timeline = Timeline::default();
+    for (index, &frequency) in melody.iter().enumerate() {
+        let start = index as f64 * 0.375 / config.cps as f64;
+        timeline.push(Note::new(start, 0.5, frequency, 0.4, 0));
     }
-    writer.finalize().unwrap();
+    timeline.push(Note::new(0.0, 1.5, 200.0, 0.3, 1));
+    timeline.push(Note::new(1.5, 1.5, 250.0, 0.3, 1));
+
+    let buffer = timeline.render(config, &[&lead, &pad]).unwrap();
+    write_sequence_to_file(config, &buffer.samples, "timeline-melody-test");
+}
+
+#[test]
+fn test_write_pattern_melody() {
+    let config = &common::test_config();
+    let tempo = TempoMap::from_config(config).unwrap();
+    let lead = UgenInstrument::new(raudio_synth::time_forms::sine_phase, 0.01, 0.2);
+    let pattern = Pattern::parse("c4 e4 [g4 b4] ~ <a3 f3> c5(3,8)").unwrap();
+    let timeline = pattern.timeline(&tempo, &Tuning::default(), 2, 0.4, 0).unwrap();
+    let buffer = timeline.render(config, &[&lead]).unwrap();
+    write_sequence_to_file(config, &buffer.samples, "pattern-melody-test");
+}
+
+#[test]
+fn test_write_arpeggiated_progression() {
+    let config = &common::test_config();
+    let tempo = TempoMap::from_config(config).unwrap();
+    let lead = UgenInstrument::new(raudio_synth::freq_forms::triangle_phase, 0.005, 0.1);
+    let chords = Key::new(57, ScaleKind::Minor).progression("i bVI bIII bVII").unwrap();
+    let arp = Arpeggiator::new(ArpMode::UpDown, 2, 8.0).unwrap();
+    let events = arp.progression(&chords, 0.5).unwrap();
+    let timeline = pattern::to_timeline(&events, &tempo, &Tuning::default(), 0.4, 0).unwrap();
+    let buffer = timeline.render_parallel(config, &[&lead], 4).unwrap();
+    write_sequence_to_file(config, &buffer.samples, "arpeggio-test");
+}
+
+fn write_sequence_to_file(config: &SynthConfig, sequence: &[f32], label: &str) {
+    let buffer = AudioBuffer::from_mono(sequence.to_vec(), config.sample_rate);
+    let options = WavOptions::new(WavFormat::Int24);
+    let filename = common::test_audio_name(config, label);
+    raudio_synth::wav::write_wav(&buffer, &filename, &options).unwrap();
     println!("Completed writing test waveform {}", filename);
-}
\ No newline at end of file
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use raudio_synth::buffer::AudioBuffer;
+use raudio_synth::pan::PanLaw;
+use raudio_synth::render::PannedSource;
+
+#[test]
+fn test_write_stereo_panned() {
+    let config = common::test_config();
+    let sources = [
+        PannedSource { ugen: raudio_synth::time_forms::sine, freq: 300.0, amp: 0.3, pan: -0.8 },
+        PannedSource { ugen: raudio_synth::time_forms::sine, freq: 450.0, amp: 0.3, pan: 0.0 },
+        PannedSource { ugen: raudio_synth::freq_forms::triangle, freq: 600.0, amp: 0.3, pan: 0.8 },
+    ];
+
+    for (name, law) in [("linear", PanLaw::Linear), ("constant-power", PanLaw::ConstantPower), ("compromise", PanLaw::Compromise), ("linear-sum", PanLaw::LinearSum)] {
+        let buffer = raudio_synth::render::render_stereo(&config, &sources, law, config.sample_rate).unwrap();
+        assert_eq!(buffer.channels, 2);
+        assert_eq!(buffer.frames(), config.sample_rate as usize);
+
+        let filename = common::test_audio_name_channels(&config, &format!("stereo_{}", name), 2);
+        buffer.write_wav(&filename).unwrap();
+
+        let loaded = AudioBuffer::read_wav(&filename).unwrap();
+        assert_eq!(loaded, buffer);
+        println!("Completed writing test waveform {}", filename);
+    }
+}
@@ -13,7 +13,7 @@ fn test_render_time() {
 
     for (name, func) in &shapes_map {
         let label = common::test_audio_name(&config, &format!("time_form_{}", name));
-        let filename = raudio_synth::render::render_ugen(&config, func, &label);
+        let filename = raudio_synth::render::render_ugen(&config, func, &label).unwrap();
         println!("Completed writing test waveform {}", filename);
     }
 }